@@ -7,6 +7,9 @@ if [[ -z "$REHASH_SESSION_ID" ]]; then
     export REHASH_SESSION_ID="$(ps -o ppid= -p $$ | tr -d ' ')_$(date +%s)"
 fi
 
+# Tell rehash which shell recorded each command
+export REHASH_SHELL=bash
+
 # Helper function to build rehash command with multi-source support
 _rehash_build_cmd() {
     local hostname=$(hostname)
@@ -37,122 +40,167 @@ _rehash_build_cmd() {
     echo "$cmd"
 }
 
-# AIDEV-NOTE: capture command using history
-_rehash_precmd() {
-    local exit_code=$?
+# Whether $1 is one of the commands in PROMPT_COMMAND (a string or, since
+# bash 5.1, an array)
+_rehash_in_prompt_command() {
+    local IFS=$'\n;'
+    local -a entries
+    local entry
+    read -rd '' -a entries <<< "${PROMPT_COMMAND[*]}"
+    for entry in "${entries[@]}"; do
+        entry="${entry#"${entry%%[![:space:]]*}"}"
+        entry="${entry%"${entry##*[![:space:]]}"}"
+        [[ "$entry" == "$1" ]] && return 0
+    done
+    return 1
+}
+
+# AIDEV-NOTE: bash has no preexec hook, so command start is recorded from the
+# DEBUG trap; only the first simple command after a prompt starts an entry.
+# Hooks added to PROMPT_COMMAND after ours (direnv, starship, vte) run after
+# the flag is set and fire the trap too, so they are skipped like bash-preexec
+# does rather than being taken for the user's command.
+_rehash_preexec() {
+    [[ -z "$_REHASH_AT_PROMPT" ]] && return
+    [[ -n "$COMP_LINE" ]] && return
+    _rehash_in_prompt_command "$BASH_COMMAND" && return
+    unset _REHASH_AT_PROMPT
+
+    local hist_line=$(HISTTIMEFORMAT= history 1)
+    local hist_num=$(sed 's/^ *\([0-9]*\).*/\1/' <<< "$hist_line")
     
-    # Skip during shell initialization
-    if [[ -z "$_REHASH_INITIALIZED" ]]; then
-        _REHASH_INITIALIZED=1
-        return
-    fi
+    # History didn't advance: empty command line, nothing to record
+    [[ "$hist_num" == "$_REHASH_LAST_HISTNUM" ]] && return
+    _REHASH_LAST_HISTNUM="$hist_num"
     
-    # Get the last command from history
-    local last_cmd=$(history 1 | sed 's/^ *[0-9]* *//')
+    local this_cmd=$(sed 's/^ *[0-9]* *//' <<< "$hist_line")
     
     # Skip problematic commands
-    if [[ -n "$last_cmd" && 
-          "$last_cmd" != rehash* && 
-          "$last_cmd" != "_rehash_precmd" &&
-          "$last_cmd" != "'" &&
-          "$last_cmd" != '"' &&
-          ${#last_cmd} -gt 1 ]]; then
-        eval "$(_rehash_build_cmd) add \"$last_cmd\" --exit-code \"$exit_code\"" 2>/dev/null || true
+    if [[ -n "$this_cmd" && 
+          "$this_cmd" != rehash* && 
+          "$this_cmd" != "'" &&
+          "$this_cmd" != '"' &&
+          ${#this_cmd} -gt 1 ]]; then
+        _REHASH_ENTRY_ID=$(eval "$(_rehash_build_cmd) start -- \"\$this_cmd\"" 2>/dev/null)
     fi
 }
 
-# AIDEV-NOTE: interactive search with Ctrl+R (global scope)
-_rehash_search() {
-    local selected
-    # Get current command line as prefix
-    local current_command="${READLINE_LINE}"
-    # Use temp file to capture result
-    local temp_file="/tmp/rehash_result_$$"
+# AIDEV-NOTE: record exit code, end time and duration after execution
+_rehash_precmd() {
+    local exit_code=$?
     
-    # Run rehash interactively - let it take control of terminal
-    if [[ -n "$current_command" ]]; then
-        eval "$(_rehash_build_cmd) interactive --scope global --prefix \"$current_command\" --output-file \"$temp_file\""
-    else
-        eval "$(_rehash_build_cmd) interactive --scope global --output-file \"$temp_file\""
+    if [[ -n "$_REHASH_ENTRY_ID" ]]; then
+        eval "$(_rehash_build_cmd) end \"\$_REHASH_ENTRY_ID\" --exit-code \"\$exit_code\"" 2>/dev/null || true
+        unset _REHASH_ENTRY_ID
     fi
     
-    # Read result from temp file
-    if [[ -f "$temp_file" ]]; then
-        selected=$(cat "$temp_file")
-        rm -f "$temp_file"
-    fi
-    if [[ -n "$selected" ]]; then
-        READLINE_LINE="$selected"
-        READLINE_POINT=${#READLINE_LINE}
+    # Skip commands already in history at shell initialization
+    if [[ -z "$_REHASH_INITIALIZED" ]]; then
+        _REHASH_INITIALIZED=1
+        _REHASH_LAST_HISTNUM=$(HISTTIMEFORMAT= history 1 | sed 's/^ *\([0-9]*\).*/\1/')
     fi
+    
+    _REHASH_AT_PROMPT=1
 }
 
-# AIDEV-NOTE: directory-local search with Ctrl+T
-_rehash_search_local() {
-    local selected
+# AIDEV-NOTE: run the interactive search in a scope and apply its result.
+# The output file holds the action (execute or edit) on the first line and the
+# command after it. bind -x functions can't accept the line themselves, so
+# each search key is a macro that runs this and then presses \C-x\C-_a, which
+# is rebound here to accept-line or a harmless redraw.
+_rehash_search_in_scope() {
+    local scope="$1"
     # Get current command line as prefix
     local current_command="${READLINE_LINE}"
     # Use temp file to capture result
     local temp_file="/tmp/rehash_result_$$"
+    local result action selected
+    
+    bind '"\C-x\C-_a": redraw-current-line'
     
     # Run rehash interactively - let it take control of terminal
     if [[ -n "$current_command" ]]; then
-        eval "$(_rehash_build_cmd) interactive --scope local --prefix \"$current_command\" --output-file \"$temp_file\""
+        eval "$(_rehash_build_cmd) interactive --scope \"\$scope\" --prefix \"\$current_command\" --output-file \"\$temp_file\""
     else
-        eval "$(_rehash_build_cmd) interactive --scope local --output-file \"$temp_file\""
+        eval "$(_rehash_build_cmd) interactive --scope \"\$scope\" --output-file \"\$temp_file\""
     fi
     
     # Read result from temp file
-    if [[ -f "$temp_file" ]]; then
-        selected=$(cat "$temp_file")
-        rm -f "$temp_file"
-    fi
+    [[ -f "$temp_file" ]] || return
+    # (not $(<file): inside bind -x on bash 5.2 that truncates the accepted line)
+    result=$(cat "$temp_file")
+    rm -f "$temp_file"
+    action="${result%%$'\n'*}"
+    selected="${result#*$'\n'}"
+    
     if [[ -n "$selected" ]]; then
         READLINE_LINE="$selected"
         READLINE_POINT=${#READLINE_LINE}
+        if [[ "$action" == "execute" ]]; then
+            bind '"\C-x\C-_a": accept-line'
+        fi
     fi
 }
 
+# AIDEV-NOTE: interactive search with Ctrl+R (global scope)
+_rehash_search() {
+    _rehash_search_in_scope global
+}
+
+# AIDEV-NOTE: directory-local search with Ctrl+T
+_rehash_search_local() {
+    _rehash_search_in_scope local
+}
+
 # AIDEV-NOTE: session search with Alt+R
 _rehash_search_session() {
-    local selected
-    # Get current command line as prefix
-    local current_command="${READLINE_LINE}"
-    # Use temp file to capture result
-    local temp_file="/tmp/rehash_result_$$"
-    
-    # Run rehash interactively - let it take control of terminal
-    if [[ -n "$current_command" ]]; then
-        eval "$(_rehash_build_cmd) interactive --scope session --prefix \"$current_command\" --output-file \"$temp_file\""
-    else
-        eval "$(_rehash_build_cmd) interactive --scope session --output-file \"$temp_file\""
-    fi
-    
-    # Read result from temp file
-    if [[ -f "$temp_file" ]]; then
-        selected=$(cat "$temp_file")
-        rm -f "$temp_file"
-    fi
-    if [[ -n "$selected" ]]; then
-        READLINE_LINE="$selected"
-        READLINE_POINT=${#READLINE_LINE}
+    _rehash_search_in_scope session
+}
+
+# AIDEV-NOTE: a shell has one DEBUG trap, so keep running whatever was set
+# before ours. bash hides the caller's DEBUG trap from sourced files and
+# functions, so it is read by PROMPT_COMMAND at the first prompt instead;
+# `trap -p` prints it quoted for reuse, as `trap -- '<command>' DEBUG`.
+_REHASH_INSTALL_TRAP=$'_REHASH_PREVIOUS_TRAP=$(trap -p DEBUG)\n_rehash_install_trap\n'
+_rehash_install_trap() {
+    PROMPT_COMMAND="${PROMPT_COMMAND#"$_REHASH_INSTALL_TRAP"}"
+    local -a words
+    eval "words=($_REHASH_PREVIOUS_TRAP)"
+    local previous="${words[2]:-}"
+    unset _REHASH_PREVIOUS_TRAP
+
+    if [[ -z "$previous" ]]; then
+        trap '_rehash_preexec' DEBUG
+    elif [[ "$previous" != *_rehash_preexec* ]]; then
+        trap "$previous"$'\n''_rehash_preexec' DEBUG
     fi
 }
 
 # Set up hooks
 if [[ "$BASH_VERSION" ]]; then
-    # Use PROMPT_COMMAND for capturing commands from history
-    if [[ -z "$PROMPT_COMMAND" ]]; then
-        PROMPT_COMMAND="_rehash_precmd"
+    if [[ -n "${bash_preexec_imported:-}${__bp_imported:-}" ]]; then
+        # bash-preexec owns the DEBUG trap and PROMPT_COMMAND; join its hooks
+        preexec_functions+=(_rehash_preexec)
+        precmd_functions+=(_rehash_precmd)
     else
-        PROMPT_COMMAND="$PROMPT_COMMAND; _rehash_precmd"
+        # Use PROMPT_COMMAND to finish commands and the DEBUG trap to start them
+        if [[ -z "$PROMPT_COMMAND" ]]; then
+            PROMPT_COMMAND="_rehash_precmd"
+        else
+            PROMPT_COMMAND="$PROMPT_COMMAND; _rehash_precmd"
+        fi
+        PROMPT_COMMAND="$_REHASH_INSTALL_TRAP$PROMPT_COMMAND"
     fi
     
-    # Set up key bindings
-    bind -x '"\C-r": _rehash_search'
-    bind -x '"\C-t": _rehash_search_local'
-    bind -x '"\er": _rehash_search_session'  # Alt+R
+    # Set up key bindings: each key runs its widget, then the accept hook
+    bind -x '"\C-x\C-_r": _rehash_search'
+    bind -x '"\C-x\C-_t": _rehash_search_local'
+    bind -x '"\C-x\C-_s": _rehash_search_session'
+    bind '"\C-x\C-_a": redraw-current-line'
+    bind '"\C-r": "\C-x\C-_r\C-x\C-_a"'
+    bind '"\C-t": "\C-x\C-_t\C-x\C-_a"'
+    bind '"\er": "\C-x\C-_s\C-x\C-_a"'  # Alt+R
 fi
 
 # Export functions for subshells
-export -f _rehash_build_cmd _rehash_precmd _rehash_search _rehash_search_local _rehash_search_session
\ No newline at end of file
+export -f _rehash_build_cmd _rehash_in_prompt_command _rehash_preexec _rehash_precmd _rehash_search_in_scope _rehash_search _rehash_search_local _rehash_search_session
\ No newline at end of file
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+
+use crate::history::SearchScope;
+use crate::keymap::{Keymap, Preset};
+use crate::matcher::MatchMode;
+use crate::ranking::RankingWeights;
+use crate::search::{Height, JoinStyle, PickerOptions, PreviewLayout};
+use crate::theme::Theme;
+
+/// Settings from `~/.config/rehash/config.toml`. Every key is optional and
+/// command line flags take precedence.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub search: SearchConfig,
+    pub ranking: RankingWeights,
+    pub history: HistoryConfig,
+    pub ui: UiConfig,
+    pub keymap: KeymapConfig,
+    pub storage: StorageConfig,
+    /// Custom themes: styles to change on top of an optional `base` theme
+    pub themes: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    pub scope: SearchScope,
+    pub mode: MatchMode,
+    /// Collapse identical commands into one row
+    pub dedup: bool,
+    /// Results printed by `rehash search`
+    pub max_results: usize,
+    /// Results listed by the interactive search for a non-empty query
+    pub interactive_limit: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            scope: SearchScope::Global,
+            mode: MatchMode::Fuzzy,
+            dedup: true,
+            max_results: 20,
+            interactive_limit: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Regular expressions; matching commands are not recorded
+    pub ignore: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    /// `full` for the alternate screen, or lines (`20`) or a percentage
+    /// (`"40%"`) for a picker drawn below the cursor
+    pub height: Height,
+    pub preview: PreviewLayout,
+    pub join: JoinStyle,
+    /// Fraction of the list kept between the selection and the edge when scrolling
+    pub scroll_threshold: f64,
+    /// `dark`, `light`, `no-color` or a custom theme; without one, `no-color`
+    /// if `NO_COLOR` is set and `dark` otherwise
+    pub theme: Option<String>,
+    /// Styles changed on top of the theme
+    pub colors: BTreeMap<String, String>,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            height: Height::Full,
+            preview: PreviewLayout::Hidden,
+            join: JoinStyle::Newline,
+            scroll_threshold: 0.35,
+            theme: None,
+            colors: BTreeMap::new(),
+        }
+    }
+}
+
+/// Keys of the interactive search: a preset plus overrides mapping key names
+/// (`ctrl-a`, `f1`, `shift-tab`, `G`) to action names, or to `"none"`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub preset: Preset,
+    /// The only mode for emacs, insert mode for vi
+    pub bindings: BTreeMap<String, String>,
+    /// vi normal mode
+    pub normal: BTreeMap<String, String>,
+}
+
+impl KeymapConfig {
+    pub fn keymap(&self) -> Result<Keymap> {
+        Keymap::new(self.preset, &self.bindings, &self.normal)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// History database path or URL, as for `--database`
+    pub database: Option<String>,
+    /// Additional read-only databases, as for `--read-sources`
+    pub read_sources: Vec<String>,
+}
+
+impl Config {
+    /// `$REHASH_CONFIG`, else `$XDG_CONFIG_HOME/rehash/config.toml`, else
+    /// `~/.config/rehash/config.toml`.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("REHASH_CONFIG").filter(|path| !path.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+        Some(base.join("rehash").join("config.toml"))
+    }
+
+    /// Load the config file, falling back to the defaults if there is none.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Catch bad patterns and styles at startup rather than mid-search.
+    fn validate(&self) -> Result<()> {
+        self.ignore_patterns()?;
+        self.theme()?.styles()?;
+        self.keymap.keymap()?;
+        Ok(())
+    }
+
+    pub fn ignore_patterns(&self) -> Result<Vec<Regex>> {
+        self.history
+            .ignore
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("invalid ignore pattern '{}'", pattern))
+            })
+            .collect()
+    }
+
+    /// The theme in use, following <https://no-color.org>: an explicit
+    /// `ui.theme` wins over `NO_COLOR`.
+    pub fn theme_name(&self) -> &str {
+        match &self.ui.theme {
+            Some(name) => name,
+            None if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) => "no-color",
+            None => "dark",
+        }
+    }
+
+    pub fn theme(&self) -> Result<Theme> {
+        Theme::resolve(self.theme_name(), &self.themes)?
+            .with_overrides(&self.ui.colors)
+            .context("in [ui.colors]")
+    }
+
+    pub fn picker_options(&self) -> Result<PickerOptions> {
+        Ok(PickerOptions {
+            join: self.ui.join,
+            height: self.ui.height,
+            preview: self.ui.preview,
+            limit: self.search.interactive_limit,
+            scroll_threshold: self.ui.scroll_threshold,
+            styles: self.theme()?.styles()?,
+            keymap: self.keymap.keymap()?,
+        })
+    }
+
+    /// The effective configuration as TOML, for `rehash config show`.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
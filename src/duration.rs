@@ -0,0 +1,105 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::Duration;
+
+/// Parse a human duration such as `90s`, `15m`, `12h`, `30d`, `2w` or `1y`.
+///
+/// Several parts may be combined (`1h30m`); a bare number is taken as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    if input.is_empty() {
+        bail!("empty duration");
+    }
+
+    let mut total = Duration::zero();
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            bail!("invalid duration '{}': expected a number", input);
+        }
+        let value: i64 = rest[..digits]
+            .parse()
+            .map_err(|_| anyhow!("invalid duration '{}'", input))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        rest = &rest[unit_len..];
+
+        let part = match unit {
+            "" | "s" | "sec" | "secs" => Duration::try_seconds(value),
+            "ms" => Duration::try_milliseconds(value),
+            "m" | "min" | "mins" => Duration::try_minutes(value),
+            "h" | "hr" | "hrs" => Duration::try_hours(value),
+            "d" | "day" | "days" => Duration::try_days(value),
+            "w" | "wk" | "wks" => Duration::try_weeks(value),
+            "mo" => value.checked_mul(30).and_then(Duration::try_days),
+            "y" | "yr" | "yrs" => value.checked_mul(365).and_then(Duration::try_days),
+            other => bail!("invalid duration '{}': unknown unit '{}'", input, other),
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .ok_or_else(|| anyhow!("invalid duration '{}': too long", input))?;
+    }
+
+    Ok(total)
+}
+
+/// Format a command duration compactly for display, e.g. `850ms`, `12s`,
+/// `3m05s` or `1h02m`.
+pub fn format_duration(duration: Duration) -> String {
+    let ms = duration.num_milliseconds().max(0);
+    let secs = ms / 1000;
+
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units_and_combinations() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::seconds(90));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::milliseconds(250));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::days(14));
+        assert_eq!(parse_duration("1y1mo").unwrap(), Duration::days(395));
+
+        for input in ["", "d", "3x", "1h-2m"] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn huge_durations_are_errors() {
+        for input in [
+            "99999999999999d",
+            "9999999999999999w",
+            "9223372036854775807y",
+            "99999999999999999999s",
+            "100000000000000h100000000000000h",
+        ] {
+            let err = parse_duration(input).unwrap_err().to_string();
+            assert!(err.starts_with("invalid duration"), "{}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn formats_compactly() {
+        assert_eq!(format_duration(Duration::milliseconds(850)), "850ms");
+        assert_eq!(format_duration(Duration::seconds(12)), "12s");
+        assert_eq!(format_duration(Duration::seconds(185)), "3m05s");
+        assert_eq!(format_duration(Duration::minutes(62)), "1h02m");
+        assert_eq!(format_duration(Duration::seconds(-5)), "0ms");
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::Result;
+use chrono::{Local, SecondsFormat};
+use clap::ValueEnum;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::duration::format_duration;
+use crate::history::HistoryEntry;
+
+/// Output formats for `rehash export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// One JSON object per line, as rehash stores them
+    Jsonl,
+    /// Comma-separated values with a header row
+    Csv,
+    /// Tab-separated values with a header row; tabs, newlines and
+    /// backslashes in fields are escaped as \t, \n and \\
+    Tsv,
+    /// ~/.bash_history with `#<epoch>` timestamps (for HISTTIMEFORMAT)
+    Bash,
+    /// ~/.zsh_history in EXTENDED_HISTORY format
+    ZshExtended,
+    /// fish_history
+    Fish,
+    /// A Markdown document with one code block per command
+    Markdown,
+}
+
+impl ExportFormat {
+    /// The format a file name asks for by its extension: `.csv`, `.tsv`,
+    /// `.md`, `.sh` (bash history, which runs as a script), else JSON Lines.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => ExportFormat::Csv,
+            Some("tsv") => ExportFormat::Tsv,
+            Some("md" | "markdown") => ExportFormat::Markdown,
+            Some("sh" | "bash") => ExportFormat::Bash,
+            _ => ExportFormat::Jsonl,
+        }
+    }
+}
+
+const COLUMNS: [&str; 12] = [
+    "timestamp",
+    "command",
+    "directory",
+    "exit_code",
+    "duration_ms",
+    "session_id",
+    "hostname",
+    "user",
+    "shell",
+    "project_root",
+    "branch",
+    "count",
+];
+
+/// Open `path` for an export, creating it readable by the owner only.
+///
+/// AIDEV-NOTE: an export holds every command, secrets typed into them
+/// included, so it is private whatever the umask; an existing file keeps
+/// the mode it has.
+pub fn create_output(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    Ok(options.open(path)?)
+}
+
+/// Write `entries` (oldest first) to `out` in `format`.
+pub fn write_entries(format: ExportFormat, entries: &[HistoryEntry], out: &mut dyn Write) -> Result<()> {
+    match format {
+        ExportFormat::Jsonl => {
+            for entry in entries {
+                writeln!(out, "{}", serde_json::to_string(entry)?)?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "{}", COLUMNS.join(","))?;
+            for entry in entries {
+                let fields: Vec<_> = fields(entry).iter().map(|field| csv_field(field)).collect();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        }
+        ExportFormat::Tsv => {
+            writeln!(out, "{}", COLUMNS.join("\t"))?;
+            for entry in entries {
+                let fields: Vec<_> = fields(entry).iter().map(|field| tsv_field(field)).collect();
+                writeln!(out, "{}", fields.join("\t"))?;
+            }
+        }
+        ExportFormat::Bash => {
+            // AIDEV-NOTE: bash reads everything up to the next timestamp as
+            // one entry, so multi-line commands survive as they are
+            for entry in entries {
+                writeln!(out, "#{}\n{}", entry.timestamp.timestamp(), entry.command)?;
+            }
+        }
+        ExportFormat::ZshExtended => {
+            for entry in entries {
+                let seconds = entry.duration_ms.unwrap_or(0) / 1000;
+                let line = format!(
+                    ": {}:{};{}\n",
+                    entry.timestamp.timestamp(),
+                    seconds,
+                    entry.command.replace('\n', "\\\n")
+                );
+                out.write_all(&metafy(line.as_bytes()))?;
+            }
+        }
+        ExportFormat::Fish => {
+            for entry in entries {
+                let command = entry.command.replace('\\', "\\\\").replace('\n', "\\n");
+                writeln!(out, "- cmd: {}\n  when: {}", command, entry.timestamp.timestamp())?;
+            }
+        }
+        ExportFormat::Markdown => write_markdown(entries, out)?,
+    }
+    Ok(())
+}
+
+/// Column values for CSV and TSV, in `COLUMNS` order.
+fn fields(entry: &HistoryEntry) -> [String; 12] {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+    [
+        entry.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        entry.command.clone(),
+        entry.directory.clone(),
+        entry.exit_code.to_string(),
+        entry.duration_ms.map(|ms| ms.to_string()).unwrap_or_default(),
+        entry.session_id.clone(),
+        optional(&entry.hostname),
+        optional(&entry.user),
+        optional(&entry.shell),
+        optional(&entry.project_root),
+        optional(&entry.branch),
+        entry.count.to_string(),
+    ]
+}
+
+/// Quote fields containing separators, quotes or line breaks (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn tsv_field(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// The inverse of zsh's unmetafy: NUL and bytes 0x83-0xa2 become 0x83
+/// followed by the byte xor 32.
+fn metafy(bytes: &[u8]) -> Vec<u8> {
+    const META: u8 = 0x83;
+
+    let mut out = Vec::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte == 0 || (META..=0xa2).contains(&byte) {
+            out.extend([META, byte ^ 32]);
+        } else {
+            out.push(byte);
+        }
+    }
+    out
+}
+
+fn write_markdown(entries: &[HistoryEntry], out: &mut dyn Write) -> Result<()> {
+    writeln!(out, "# Shell history")?;
+    for entry in entries {
+        let mut details = vec![
+            entry.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            format!("in `{}`", entry.directory),
+        ];
+        if entry.exit_code != 0 {
+            details.push(format!("exit {}", entry.exit_code));
+        }
+        if let Some(duration) = entry.duration() {
+            details.push(format_duration(duration));
+        }
+
+        // AIDEV-NOTE: a fence longer than any backtick run in the command
+        // keeps commands that themselves contain ``` intact
+        let longest_run = entry
+            .command
+            .split(|c| c != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest_run.max(2) + 1);
+        writeln!(out, "\n{}\n\n{}sh\n{}\n{}", details.join(", "), fence, entry.command, fence)?;
+    }
+    Ok(())
+}
@@ -1,13 +1,21 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use clap::ValueEnum;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::path::{Path, PathBuf};
 
-use crate::search::FuzzySearcher;
-use crate::storage::Storage;
+use crate::import::ImportSource;
+use crate::matcher::MatchMode;
+use crate::project;
+use crate::query::Query;
+use crate::ranking::{Ranker, RankingWeights};
+use crate::search::{FuzzySearcher, PickerOptions, Selection};
+use crate::storage::{self, CompactPolicy, CompactReport, EntryFilter, Storage};
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SearchScope {
     /// Search all history across all directories and sessions
     Global,
@@ -15,6 +23,38 @@ pub enum SearchScope {
     Session,
     /// Search current directory across all sessions
     Local,
+    /// Search commands recorded on this host
+    Host,
+    /// Search the enclosing git/hg project, from its root down
+    Project,
+}
+
+/// Where rehash is running from; decides what each `SearchScope` matches.
+#[derive(Debug, Clone)]
+pub struct ScopeContext {
+    pub current_dir: String,
+    pub session_id: String,
+    pub hostname: Option<String>,
+    pub project_root: Option<String>,
+}
+
+impl ScopeContext {
+    pub fn filter(&self, scope: SearchScope) -> EntryFilter {
+        match scope {
+            SearchScope::Global => EntryFilter::all(),
+            SearchScope::Session => EntryFilter::session(&self.session_id),
+            SearchScope::Local => EntryFilter::directory(&self.current_dir),
+            // AIDEV-NOTE: entries recorded before hostnames were captured have
+            // none and therefore never match the host scope
+            SearchScope::Host => EntryFilter::hostname(self.hostname.as_deref().unwrap_or_default()),
+            // AIDEV-NOTE: matching on directory rather than the recorded
+            // project_root also picks up entries from before roots were
+            // recorded; outside a project this is the same as Local
+            SearchScope::Project => EntryFilter::directory(
+                self.project_root.as_deref().unwrap_or(&self.current_dir),
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +64,90 @@ pub struct HistoryEntry {
     pub directory: String,
     pub exit_code: i32,
     pub session_id: String,
+    /// Machine, user and shell the command ran under (absent on old entries)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// Enclosing git/hg project root and its branch at the time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_root: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// When the command finished, for entries captured with `start`/`end`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_timestamp: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// How many runs this entry stands for once duplicates have been compacted
+    #[serde(default = "default_count", skip_serializing_if = "is_single")]
+    pub count: u32,
+}
+
+impl HistoryEntry {
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        self.duration_ms
+            .map(|ms| chrono::Duration::milliseconds(ms.min(i64::MAX as u64) as i64))
+    }
+
+    /// A single run in session `s1` with everything optional left out.
+    #[cfg(test)]
+    pub fn test(command: &str, directory: &str, exit_code: i32, timestamp: DateTime<Utc>) -> Self {
+        HistoryEntry {
+            command: command.to_string(),
+            timestamp,
+            directory: directory.to_string(),
+            exit_code,
+            session_id: "s1".to_string(),
+            hostname: None,
+            user: None,
+            shell: None,
+            project_root: None,
+            branch: None,
+            end_timestamp: None,
+            duration_ms: None,
+            count: 1,
+        }
+    }
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn is_single(count: &u32) -> bool {
+    *count == 1
+}
+
+fn pending_path(dir: &std::path::Path, id: &str) -> Result<PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        anyhow::bail!("Invalid entry id '{}'", id);
+    }
+
+    std::fs::create_dir_all(dir)?;
+
+    Ok(dir.join(format!("{}.json", id)))
+}
+
+fn remove_stale_pending(dir: &std::path::Path) {
+    const MAX_AGE: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_stale = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > MAX_AGE);
+        if is_stale {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -35,9 +159,42 @@ pub struct HistoryStats {
 
 pub struct HistoryManager {
     storage: Storage,
-    searcher: FuzzySearcher,
-    current_dir: String,
-    session_id: String,
+    context: ScopeContext,
+    user: Option<String>,
+    shell: Option<String>,
+    branch: Option<String>,
+    ranking: RankingWeights,
+    /// Commands matching any of these are not recorded
+    ignore: Vec<Regex>,
+}
+
+fn detect_hostname() -> Option<String> {
+    gethostname::gethostname()
+        .into_string()
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+fn detect_user() -> Option<String> {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+}
+
+// AIDEV-NOTE: the shell integration exports REHASH_SHELL; $SHELL is only the
+// login shell and may differ from the one actually running the command
+fn detect_shell() -> Option<String> {
+    env::var("REHASH_SHELL")
+        .ok()
+        .or_else(|| {
+            env::var("SHELL").ok().and_then(|path| {
+                std::path::Path::new(&path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+            })
+        })
+        .filter(|shell| !shell.is_empty())
 }
 
 impl HistoryManager {
@@ -51,91 +208,215 @@ impl HistoryManager {
             format!("{}_{}", std::process::id(), Utc::now().timestamp())
         });
 
+        let project = project::detect(Path::new(&current_dir));
+
         Ok(Self {
             storage: Storage::new(database_path, read_sources)?,
-            searcher: FuzzySearcher::new(),
-            current_dir,
-            session_id,
+            context: ScopeContext {
+                current_dir,
+                session_id,
+                hostname: detect_hostname(),
+                project_root: project.as_ref().map(|p| p.root.clone()),
+            },
+            user: detect_user(),
+            shell: detect_shell(),
+            branch: project.and_then(|p| p.branch),
+            ranking: RankingWeights::default(),
+            ignore: Vec::new(),
         })
     }
 
-    pub fn add_command(&mut self, command: &str, exit_code: i32) -> Result<()> {
-        let entry = HistoryEntry {
+    fn new_entry(&self, command: &str, exit_code: i32) -> HistoryEntry {
+        HistoryEntry {
             command: command.to_string(),
             timestamp: Utc::now(),
-            directory: self.current_dir.clone(),
+            directory: self.context.current_dir.clone(),
             exit_code,
-            session_id: self.session_id.clone(),
-        };
+            session_id: self.context.session_id.clone(),
+            hostname: self.context.hostname.clone(),
+            user: self.user.clone(),
+            shell: self.shell.clone(),
+            project_root: self.context.project_root.clone(),
+            branch: self.branch.clone(),
+            end_timestamp: None,
+            duration_ms: None,
+            count: 1,
+        }
+    }
 
-        self.storage.add_entry(entry)
+    pub fn set_ignore_patterns(&mut self, patterns: Vec<Regex>) {
+        self.ignore = patterns;
     }
 
-    pub fn search(&self, query: &str, scope: SearchScope, max_results: usize) -> Result<Vec<HistoryEntry>> {
-        let entries = self.get_entries_by_scope(scope)?;
-        Ok(self.searcher.search(query, &entries, max_results))
+    fn is_ignored(&self, command: &str) -> bool {
+        self.ignore.iter().any(|pattern| pattern.is_match(command))
     }
 
-    pub fn list_recent(&self, scope: SearchScope, max_results: usize) -> Result<Vec<HistoryEntry>> {
-        let mut entries = self.get_entries_by_scope(scope)?;
+    pub fn add_command(&mut self, command: &str, exit_code: i32) -> Result<()> {
+        if self.is_ignored(command) {
+            return Ok(());
+        }
+        let entry = self.new_entry(command, exit_code);
+        self.storage.add_entry(entry)
+    }
 
-        // AIDEV-NOTE: sort by timestamp ascending (chronological order) to match interactive UI
-        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        // Take the most recent entries (from the end)
-        if entries.len() > max_results {
-            entries = entries.into_iter().rev().take(max_results).collect::<Vec<_>>();
-            entries.reverse(); // Put back in chronological order
+    /// First half of two-phase capture, called before the command runs.
+    ///
+    /// AIDEV-NOTE: the in-flight entry is parked in a small pending file rather
+    /// than the store, so `end` is a single append instead of a rewrite.
+    /// Returns the id to hand back to `end_command`, or `None` if the
+    /// command is ignored.
+    pub fn start_command(&self, command: &str) -> Result<Option<String>> {
+        if self.is_ignored(command) {
+            return Ok(None);
         }
-        Ok(entries)
+        let entry = self.new_entry(command, 0);
+        let id = format!(
+            "{:x}-{:x}",
+            entry.timestamp.timestamp_nanos_opt().unwrap_or_default(),
+            std::process::id()
+        );
+
+        let path = pending_path(self.storage.pending_dir(), &id)?;
+        std::fs::write(&path, serde_json::to_string(&entry)?)?;
+
+        // AIDEV-NOTE: commands that never reach `end` (the shell exited or was
+        // killed) would otherwise pile up in the pending directory
+        if let Some(dir) = path.parent() {
+            remove_stale_pending(dir);
+        }
+
+        Ok(Some(id))
+    }
+
+    /// Second half of two-phase capture, called once the command finished.
+    pub fn end_command(&self, id: &str, exit_code: i32) -> Result<()> {
+        let path = pending_path(self.storage.pending_dir(), id)?;
+        let mut entry: HistoryEntry = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        std::fs::remove_file(&path)?;
+
+        let end = Utc::now();
+        entry.exit_code = exit_code;
+        entry.duration_ms = Some(
+            end.signed_duration_since(entry.timestamp)
+                .num_milliseconds()
+                .max(0) as u64,
+        );
+        entry.end_timestamp = Some(end);
+
+        self.storage.add_entry(entry)
+    }
+
+    pub fn set_ranking_weights(&mut self, weights: RankingWeights) {
+        self.ranking = weights;
     }
 
-    pub fn interactive_search(&self, initial_scope: SearchScope) -> Result<Option<String>> {
-        self.interactive_search_with_prefix(initial_scope, None)
+    fn searcher(&self, mode: MatchMode, dedup: bool) -> FuzzySearcher {
+        FuzzySearcher::new(mode, Ranker::new(self.ranking, &self.context.current_dir), dedup)
     }
 
-    pub fn interactive_search_with_prefix(&self, initial_scope: SearchScope, prefix: Option<String>) -> Result<Option<String>> {
+    /// An empty query lists the most recent entries in the scope.
+    pub fn search(&self, query: &str, scope: SearchScope, mode: MatchMode, dedup: bool, max_results: usize) -> Result<Vec<HistoryEntry>> {
+        let query = Query::parse(query, &self.context);
+        let entries = self.get_entries_by_scope(scope)?;
+        Ok(self.searcher(mode, dedup).search(&query, &entries, max_results))
+    }
+
+    /// Entries for `rehash export`: the scope and query filters of `search`,
+    /// but every run, oldest first.
+    pub fn export(&self, query: &str, scope: SearchScope, mode: MatchMode) -> Result<Vec<HistoryEntry>> {
+        let query = Query::parse(query, &self.context);
+        let entries = self.get_entries_by_scope(scope)?;
+        Ok(self.searcher(mode, false).filter(&query, &entries))
+    }
+
+    pub fn interactive_search_with_prefix(&self, initial_scope: SearchScope, mode: MatchMode, dedup: bool, options: PickerOptions, prefix: Option<String>) -> Result<Option<Selection>> {
         use crate::search::InteractiveSearcher;
         
-        let all_entries = self.storage.get_all_entries()?;
-        let interactive = InteractiveSearcher::new_with_prefix(
-            all_entries, 
+        let mut interactive = InteractiveSearcher::new_with_prefix(
+            &self.storage,
             initial_scope, 
-            &self.current_dir, 
-            &self.session_id,
+            self.searcher(mode, dedup),
+            self.context.clone(),
             prefix
-        );
+        )?;
+        interactive.set_options(options);
         interactive.run()
     }
 
     fn get_entries_by_scope(&self, scope: SearchScope) -> Result<Vec<HistoryEntry>> {
-        match scope {
-            SearchScope::Global => self.storage.get_all_entries(),
-            SearchScope::Session => self.storage.get_session_entries(&self.session_id),
-            SearchScope::Local => self.storage.get_local_entries(&self.current_dir),
-        }
+        self.storage.get_entries(&self.context.filter(scope))
     }
 
     pub fn get_stats(&self) -> Result<HistoryStats> {
-        let all_entries = self.storage.get_all_entries()?;
-        let local_entries = self.storage.get_local_entries(&self.current_dir)?;
-        
-        let unique_commands: std::collections::HashSet<_> = all_entries
-            .iter()
-            .map(|e| &e.command)
-            .collect();
+        let store_stats = self.storage.stats()?;
+        let local_entries = self.get_entries_by_scope(SearchScope::Local)?;
 
         Ok(HistoryStats {
-            total_commands: all_entries.len(),
-            unique_commands: unique_commands.len(),
+            total_commands: store_stats.total_entries,
+            unique_commands: store_stats.unique_commands,
             local_commands: local_entries.len(),
         })
     }
 
-    pub fn clear_history(&mut self, scope: SearchScope) -> Result<()> {
-        match scope {
-            SearchScope::Global => self.storage.clear_all_history(),
-            SearchScope::Session => self.storage.clear_session_history(&self.session_id),
-            SearchScope::Local => self.storage.clear_local_history(&self.current_dir),
+    pub fn compact(&self, policy: &CompactPolicy, dry_run: bool) -> Result<CompactReport> {
+        self.storage.compact(policy, dry_run)
+    }
+
+    /// One-shot copy of existing history files into the primary store.
+    ///
+    /// Returns `(read, added)`; entries already present are skipped.
+    pub fn migrate(&self, sources: Vec<String>) -> Result<(usize, usize)> {
+        let sources = if sources.is_empty() {
+            vec![storage::default_location()?]
+        } else {
+            sources
+        };
+
+        let mut read = 0;
+        let mut added = 0;
+        for source in sources {
+            let entries = storage::open_read_source(&source)?.scan(&EntryFilter::all())?;
+            read += entries.len();
+            added += self.storage.import_entries(entries)?;
         }
+
+        Ok((read, added))
+    }
+
+    /// Copy another shell's history into the primary store.
+    ///
+    /// Returns `(read, added)` like `migrate`. Commands from shell history
+    /// files are filed under the home directory and an `import-<source>`
+    /// session, so importing the same file again adds only what is new;
+    /// commands recorded without a time are skipped if that session already
+    /// has them.
+    pub fn import(&self, source: ImportSource, path: Option<PathBuf>) -> Result<(usize, usize)> {
+        let path = match path.or_else(|| source.default_path()) {
+            Some(path) => path,
+            None => anyhow::bail!("Could not find the {} history; pass its path", source.name()),
+        };
+
+        let home = dirs::home_dir().map(|home| home.to_string_lossy().to_string());
+        let template = HistoryEntry {
+            directory: home.unwrap_or_else(|| "/".to_string()),
+            session_id: format!("import-{}", source.name()),
+            project_root: None,
+            branch: None,
+            shell: Some(source.name().to_string()).filter(|_| source != ImportSource::Atuin),
+            ..self.new_entry("", 0)
+        };
+
+        let mut imported = source.read(&path, &template)?;
+        imported.timed.retain(|entry| !self.is_ignored(&entry.command));
+        imported.untimed.retain(|entry| !self.is_ignored(&entry.command));
+        let read = imported.timed.len() + imported.untimed.len();
+        let added = self.storage.import_untimed(imported.untimed)?
+            + self.storage.import_entries(imported.timed)?;
+        Ok((read, added))
+    }
+
+    pub fn clear_history(&mut self, scope: SearchScope) -> Result<()> {
+        self.storage.clear_history(&self.context.filter(scope))
     }
 }
\ No newline at end of file
@@ -0,0 +1,396 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::history::HistoryEntry;
+use crate::search::dedup_commands;
+
+/// Another shell's (or tool's) history that `rehash import` can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportSource {
+    /// ~/.bash_history, with `#<epoch>` lines if HISTTIMEFORMAT was set
+    Bash,
+    /// ~/.zsh_history, plain or EXTENDED_HISTORY (`: start:duration;command`)
+    Zsh,
+    /// fish's ~/.local/share/fish/fish_history
+    Fish,
+    /// atuin's SQLite database, ~/.local/share/atuin/history.db
+    Atuin,
+}
+
+impl ImportSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+            Self::Atuin => "atuin",
+        }
+    }
+
+    /// Where the source keeps its history by default.
+    pub fn default_path(self) -> Option<PathBuf> {
+        let home = dirs::home_dir()?;
+        // AIDEV-NOTE: fish and atuin use the XDG layout on every platform, so
+        // dirs::data_dir() (~/Library/Application Support on macOS) is wrong here
+        let data = env::var_os("XDG_DATA_HOME")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".local").join("share"));
+
+        Some(match self {
+            Self::Bash => home.join(".bash_history"),
+            Self::Zsh => env::var_os("ZDOTDIR")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or(home)
+                .join(".zsh_history"),
+            Self::Fish => data.join("fish").join("fish_history"),
+            Self::Atuin => data.join("atuin").join("history.db"),
+        })
+    }
+
+    /// Read every command from `path`, oldest first.
+    ///
+    /// Fields the source doesn't record are copied from `template`.
+    pub fn read(self, path: &Path, template: &HistoryEntry) -> Result<Imported> {
+        let mut imported = match self {
+            Self::Bash => parse_bash(&read_text(path)?, template),
+            Self::Zsh => {
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                parse_zsh(&String::from_utf8_lossy(&unmetafy(&bytes)), template)
+            }
+            Self::Fish => parse_fish(&read_text(path)?, template),
+            Self::Atuin => Imported {
+                timed: read_atuin(path, template)?,
+                untimed: Vec::new(),
+            },
+        };
+
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        imported.date_untimed(modified);
+        Ok(imported)
+    }
+}
+
+/// Commands read from another shell's history.
+#[derive(Debug, Default)]
+pub struct Imported {
+    /// Commands with the time they ran
+    pub timed: Vec<HistoryEntry>,
+    /// Commands the source kept no time for: one entry per command, with
+    /// `count` covering its runs and a made-up timestamp
+    pub untimed: Vec<HistoryEntry>,
+}
+
+impl Imported {
+    fn push_untimed(&mut self, template: &HistoryEntry, command: String) {
+        self.untimed.push(entry_at(template, command, template.timestamp));
+    }
+
+    /// Date untimed commands a second apart in file order, ending just before
+    /// the first timed one or else the file's modification time.
+    ///
+    /// AIDEV-NOTE: timed entries are appended after untimed ones (bash only
+    /// writes `#<epoch>` lines once HISTTIMEFORMAT is set), so this keeps
+    /// them in order and lets recency ranking and compaction treat untimed
+    /// commands as about as old as the file
+    fn date_untimed(&mut self, modified: DateTime<Utc>) {
+        let end = self
+            .timed
+            .iter()
+            .map(|entry| entry.timestamp)
+            .min()
+            .map_or(modified, |first| first.min(modified));
+        let total = self.untimed.len();
+        for (index, entry) in self.untimed.iter_mut().enumerate() {
+            let before = Duration::seconds((total - index) as i64);
+            entry.timestamp = end.checked_sub_signed(before).unwrap_or(end);
+        }
+        self.untimed = dedup_commands(self.untimed.iter());
+    }
+}
+
+fn read_text(path: &Path) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn entry_at(template: &HistoryEntry, command: String, timestamp: DateTime<Utc>) -> HistoryEntry {
+    HistoryEntry {
+        command,
+        timestamp,
+        ..template.clone()
+    }
+}
+
+fn from_epoch(seconds: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, 0)
+}
+
+/// `#<epoch>` lines precede each command when HISTTIMEFORMAT is set; the
+/// lines up to the next one are a single (possibly multi-line) command, as
+/// bash itself reads them back. Without timestamps every line is a command.
+fn parse_bash(text: &str, template: &HistoryEntry) -> Imported {
+    let mut imported = Imported::default();
+    let mut timed: Option<(DateTime<Utc>, Vec<&str>)> = None;
+
+    let finish = |timed: Option<(DateTime<Utc>, Vec<&str>)>, imported: &mut Imported| {
+        if let Some((timestamp, lines)) = timed {
+            let command = lines.join("\n");
+            if !command.trim().is_empty() {
+                imported.timed.push(entry_at(template, command, timestamp));
+            }
+        }
+    };
+
+    for line in text.lines() {
+        let timestamp = line
+            .strip_prefix('#')
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok())
+            .and_then(from_epoch);
+
+        if let Some(timestamp) = timestamp {
+            finish(timed.take(), &mut imported);
+            timed = Some((timestamp, Vec::new()));
+        } else if let Some((_, lines)) = &mut timed {
+            lines.push(line);
+        } else if !line.trim().is_empty() {
+            imported.push_untimed(template, line.to_string());
+        }
+    }
+    finish(timed, &mut imported);
+
+    imported
+}
+
+/// zsh escapes NUL and bytes 0x83-0xa2 as 0x83 followed by the byte xor 32.
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    const META: u8 = 0x83;
+
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&byte) = iter.next() {
+        match byte {
+            META => out.extend(iter.next().map(|next| next ^ 32)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+/// Lines ending in a backslash continue on the next one; EXTENDED_HISTORY
+/// prefixes each command with `: <start>:<seconds>;`.
+fn parse_zsh(text: &str, template: &HistoryEntry) -> Imported {
+    let mut imported = Imported::default();
+    let mut lines = text.lines();
+
+    while let Some(first) = lines.next() {
+        let mut command = first.to_string();
+        while command.ends_with('\\') {
+            let Some(next) = lines.next() else {
+                break;
+            };
+            command.pop();
+            command.push('\n');
+            command.push_str(next);
+        }
+
+        let Some((start, seconds, rest)) = parse_extended(&command) else {
+            if !command.trim().is_empty() {
+                imported.push_untimed(template, command.trim_end_matches('\n').to_string());
+            }
+            continue;
+        };
+        if rest.trim().is_empty() {
+            continue;
+        }
+
+        let mut entry = entry_at(template, rest.trim_end_matches('\n').to_string(), start);
+        // AIDEV-NOTE: zsh only writes whole seconds and 0 for anything
+        // quicker, so a zero duration says nothing
+        if seconds > 0 {
+            entry.duration_ms = Some(seconds.saturating_mul(1000));
+            entry.end_timestamp = Duration::try_seconds(seconds as i64)
+                .and_then(|duration| start.checked_add_signed(duration));
+        }
+        imported.timed.push(entry);
+    }
+
+    imported
+}
+
+fn parse_extended(line: &str) -> Option<(DateTime<Utc>, u64, &str)> {
+    let (header, command) = line.strip_prefix(": ")?.split_once(';')?;
+    let (start, seconds) = header.split_once(':')?;
+    Some((from_epoch(start.trim().parse().ok()?)?, seconds.trim().parse().ok()?, command))
+}
+
+/// fish writes `- cmd: <command>` followed by indented `when:` and `paths:`
+/// keys, escaping backslashes and newlines in the command.
+fn parse_fish(text: &str, template: &HistoryEntry) -> Imported {
+    let mut commands: Vec<(String, Option<DateTime<Utc>>)> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(command) = line.strip_prefix("- cmd: ") {
+            commands.push((unescape_fish(command), None));
+        } else if let Some(when) = line.trim_start().strip_prefix("when: ") {
+            if let Some((_, timestamp)) = commands.last_mut() {
+                *timestamp = when.trim().parse().ok().and_then(from_epoch);
+            }
+        }
+    }
+
+    let mut imported = Imported::default();
+    for (command, timestamp) in commands {
+        match timestamp {
+            _ if command.trim().is_empty() => {}
+            Some(timestamp) => imported.timed.push(entry_at(template, command, timestamp)),
+            None => imported.push_untimed(template, command),
+        }
+    }
+    imported
+}
+
+fn unescape_fish(command: &str) -> String {
+    let mut out = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// atuin keeps nanosecond timestamps and durations (-1 when unknown) and
+/// `host:user` in its hostname column; deleted entries stay with `deleted_at` set.
+#[cfg(feature = "sqlite")]
+fn read_atuin(path: &Path, template: &HistoryEntry) -> Result<Vec<HistoryEntry>> {
+    use rusqlite::{Connection, OpenFlags};
+
+    if !path.exists() {
+        bail!("Failed to read {}: no such file", path.display());
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT timestamp, duration, exit, command, cwd, session, hostname FROM history
+             WHERE deleted_at IS NULL ORDER BY timestamp",
+        )
+        .with_context(|| format!("{} is not an atuin history database", path.display()))?;
+
+    let entries = stmt
+        .query_map([], |row| {
+            let start: i64 = row.get(0)?;
+            let duration: i64 = row.get(1)?;
+            let host: String = row.get(6)?;
+            let (hostname, user) = match host.split_once(':') {
+                Some((hostname, user)) => (hostname.to_string(), Some(user.to_string())),
+                None => (host, None),
+            };
+
+            Ok(HistoryEntry {
+                exit_code: row.get(2)?,
+                directory: row.get(4)?,
+                session_id: row.get(5)?,
+                hostname: Some(hostname).filter(|name| !name.is_empty()),
+                user: user.or_else(|| template.user.clone()),
+                end_timestamp: (duration >= 0)
+                    .then(|| DateTime::from_timestamp_nanos(start.saturating_add(duration))),
+                duration_ms: (duration >= 0).then_some((duration / 1_000_000) as u64),
+                ..entry_at(template, row.get(3)?, DateTime::from_timestamp_nanos(start))
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(entries)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn read_atuin(_path: &Path, _template: &HistoryEntry) -> Result<Vec<HistoryEntry>> {
+    bail!("rehash was built without SQLite support (enable the `sqlite` feature)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> HistoryEntry {
+        HistoryEntry {
+            session_id: "import-test".to_string(),
+            ..HistoryEntry::test("", "/home/me", 0, DateTime::UNIX_EPOCH)
+        }
+    }
+
+    fn commands(entries: &[HistoryEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.command.as_str()).collect()
+    }
+
+    #[test]
+    fn zsh_joins_continuations_and_reads_durations() {
+        let text = ": 1700000000:0;ls\n: 1700000005:3;for f in *; do\\\n  echo $f\\\ndone\nmake\n";
+        let imported = parse_zsh(text, &template());
+        let entries = &imported.timed;
+
+        assert_eq!(commands(entries), ["ls", "for f in *; do\n  echo $f\ndone"]);
+        assert_eq!(entries[0].duration_ms, None);
+        assert_eq!(entries[1].timestamp.timestamp(), 1_700_000_005);
+        assert_eq!(entries[1].duration_ms, Some(3000));
+        assert_eq!(commands(&imported.untimed), ["make"]);
+
+        // "à" is 0xc3 0xa0, and zsh escapes the second byte
+        assert_eq!(unmetafy(b"voil\xc3\x83\x80"), "voilà".as_bytes());
+    }
+
+    #[test]
+    fn bash_and_fish_commands_keep_newlines() {
+        let bash = parse_bash("ls\n#1700000000\necho a\necho b\n#1700000009\n\n", &template());
+        assert_eq!(commands(&bash.untimed), ["ls"]);
+        assert_eq!(commands(&bash.timed), ["echo a\necho b"]);
+        assert_eq!(bash.timed[0].timestamp.timestamp(), 1_700_000_000);
+
+        let fish = "- cmd: printf 'a\\\\n'\\nls\n  when: 1700000000\n  paths:\n    - a\n- cmd: pwd\n";
+        let fish = parse_fish(fish, &template());
+        assert_eq!(commands(&fish.timed), ["printf 'a\\n'\nls"]);
+        assert_eq!(fish.timed[0].timestamp.timestamp(), 1_700_000_000);
+        assert_eq!(commands(&fish.untimed), ["pwd"]);
+    }
+
+    #[test]
+    fn untimed_commands_are_dated_before_the_first_known_time() {
+        let modified = from_epoch(1_800_000_000).unwrap();
+
+        let mut plain = parse_bash("ls\nmake\nls\n", &template());
+        plain.date_untimed(modified);
+        let runs: Vec<_> = plain
+            .untimed
+            .iter()
+            .map(|entry| (entry.command.as_str(), entry.count, entry.timestamp.timestamp()))
+            .collect();
+        assert_eq!(runs, [("ls", 2, 1_799_999_999), ("make", 1, 1_799_999_998)]);
+
+        let mut mixed = parse_bash("ls\n#1700000000\nmake\n", &template());
+        mixed.date_untimed(modified);
+        assert_eq!(mixed.untimed[0].timestamp.timestamp(), 1_699_999_999);
+    }
+}
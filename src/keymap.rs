@@ -0,0 +1,565 @@
+use anyhow::{anyhow, bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Something the interactive search does in response to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Run the selected command
+    Accept,
+    /// Put the selected command on the command line to edit
+    Edit,
+    /// Leave without a result
+    Abort,
+    /// Leave the runs view, otherwise leave without a result
+    Cancel,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    /// Oldest entry in the list
+    Top,
+    /// Newest entry in the list
+    Bottom,
+    ScopeGlobal,
+    ScopeSession,
+    ScopeLocal,
+    ScopeHost,
+    ScopeProject,
+    NextScope,
+    CycleMode,
+    ToggleDedup,
+    /// List every run of the selected command, or go back to the results
+    ToggleRuns,
+    TogglePreview,
+    ToggleMark,
+    /// Mark while the query is empty, where a space would mean nothing
+    MarkOrSpace,
+    ToggleMarkAll,
+    /// Delete the selected or marked entries, after confirmation
+    DeleteEntry,
+    /// Write the selected or marked entries to a file, asking for its name
+    Export,
+    BackwardChar,
+    ForwardChar,
+    /// Move right, or edit the selected command at the end of the query
+    ForwardCharOrEdit,
+    BeginningOfLine,
+    EndOfLine,
+    BackwardWord,
+    ForwardWord,
+    BackwardDeleteChar,
+    DeleteChar,
+    UnixWordRubout,
+    UnixLineDiscard,
+    /// Switch to vi normal mode
+    ViNormal,
+    /// Back to insert mode at the cursor, after it, at the start or at the end
+    ViInsert,
+    ViAppend,
+    ViInsertBol,
+    ViAppendEol,
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Action::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(name))
+            .map_err(|_| anyhow!("unknown action '{}'", name))
+    }
+}
+
+/// A key with its Ctrl/Alt modifiers, as written in the config: `ctrl-r`,
+/// `alt-b`, `f1`, `shift-tab`, `enter`, `space`, `G`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // AIDEV-NOTE: Shift is already in the character (`G`, `$`) and in
+        // BackTab, and terminals disagree on whether they report it as well
+        let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let code = match code {
+            KeyCode::Char(c) if !modifiers.is_empty() => KeyCode::Char(c.to_ascii_lowercase()),
+            other => other,
+        };
+        Self { code, modifiers }
+    }
+
+    fn plain(code: KeyCode) -> Self {
+        Self::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> Self {
+        Self::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn alt(c: char) -> Self {
+        Self::new(KeyCode::Char(c), KeyModifiers::ALT)
+    }
+
+    fn char(c: char) -> Self {
+        Self::plain(KeyCode::Char(c))
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut rest = input;
+        let mut modifiers = KeyModifiers::NONE;
+        let mut shift = false;
+        // A lone `-` is a key, not a separator
+        while rest.len() > 1 {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("ctrl-") {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = &rest[5..];
+            } else if lower.starts_with("alt-") {
+                modifiers |= KeyModifiers::ALT;
+                rest = &rest[4..];
+            } else if lower.starts_with("shift-") {
+                shift = true;
+                rest = &rest[6..];
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "tab" if shift => KeyCode::BackTab,
+                "btab" | "backtab" => KeyCode::BackTab,
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "space" => KeyCode::Char(' '),
+                "backspace" | "bspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" | "pgup" => KeyCode::PageUp,
+                "pagedown" | "pgdn" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => bail!("unknown key '{}'", input),
+                },
+            },
+        };
+        if shift && code != KeyCode::BackTab {
+            bail!("shift only combines with tab, use the character itself: '{}'", input);
+        }
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+/// Short form for the help line: `^R`, `M-b`, `F1`, `S-Tab`, `Enter`.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "M-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "^")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::BackTab => write!(f, "S-Tab"),
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::Backspace => write!(f, "BS"),
+            KeyCode::Delete => write!(f, "Del"),
+            KeyCode::Up => write!(f, "Up"),
+            KeyCode::Down => write!(f, "Down"),
+            KeyCode::Left => write!(f, "Left"),
+            KeyCode::Right => write!(f, "Right"),
+            KeyCode::Home => write!(f, "Home"),
+            KeyCode::End => write!(f, "End"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Built-in starting points for the keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// readline-style keys; every plain key types into the query
+    #[default]
+    Emacs,
+    /// Insert mode like emacs, Esc switches to a normal mode with j/k, i/a...
+    Vi,
+}
+
+/// Bindings of one mode, in order of preference for the help line.
+type Bindings = Vec<(Key, Action)>;
+
+/// Which action each key triggers.
+///
+/// AIDEV-NOTE: keys that aren't bound type into the query in insert mode
+/// (unless Ctrl or Alt is held) and do nothing in vi normal mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    insert: Bindings,
+    /// Present only for modal (vi) keymaps
+    normal: Option<Bindings>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::preset(Preset::Emacs)
+    }
+}
+
+/// Bindings shared by emacs and vi insert mode.
+fn common_bindings() -> Bindings {
+    use Action::*;
+    vec![
+        (Key::plain(KeyCode::Enter), Accept),
+        (Key::plain(KeyCode::Tab), Edit),
+        (Key::plain(KeyCode::Right), ForwardCharOrEdit),
+        (Key::ctrl('c'), Abort),
+        (Key::plain(KeyCode::Esc), Cancel),
+        (Key::plain(KeyCode::Up), Up),
+        (Key::plain(KeyCode::Down), Down),
+        (Key::ctrl('p'), Up),
+        (Key::ctrl('n'), Down),
+        (Key::plain(KeyCode::PageUp), PageUp),
+        (Key::plain(KeyCode::PageDown), PageDown),
+        (Key::plain(KeyCode::F(1)), ScopeGlobal),
+        (Key::plain(KeyCode::F(2)), ScopeSession),
+        (Key::plain(KeyCode::F(3)), ScopeLocal),
+        (Key::plain(KeyCode::F(4)), ScopeHost),
+        (Key::plain(KeyCode::F(5)), ScopeProject),
+        (Key::plain(KeyCode::BackTab), NextScope),
+        (Key::ctrl('r'), CycleMode),
+        (Key::ctrl('t'), ToggleDedup),
+        (Key::ctrl('o'), ToggleRuns),
+        (Key::plain(KeyCode::F(6)), TogglePreview),
+        (Key::ctrl(' '), ToggleMark),
+        (Key::char(' '), MarkOrSpace),
+        (Key::alt('a'), ToggleMarkAll),
+        (Key::ctrl('d'), DeleteEntry),
+        (Key::ctrl('s'), Export),
+        (Key::plain(KeyCode::Left), BackwardChar),
+        (Key::ctrl('b'), BackwardChar),
+        (Key::ctrl('f'), ForwardChar),
+        (Key::plain(KeyCode::Home), BeginningOfLine),
+        (Key::ctrl('a'), BeginningOfLine),
+        (Key::plain(KeyCode::End), EndOfLine),
+        (Key::ctrl('e'), EndOfLine),
+        (Key::alt('b'), BackwardWord),
+        (Key::alt('f'), ForwardWord),
+        (Key::plain(KeyCode::Backspace), BackwardDeleteChar),
+        (Key::plain(KeyCode::Delete), DeleteChar),
+        (Key::ctrl('w'), UnixWordRubout),
+        (Key::ctrl('u'), UnixLineDiscard),
+    ]
+}
+
+fn vi_normal_bindings() -> Bindings {
+    use Action::*;
+    vec![
+        (Key::plain(KeyCode::Enter), Accept),
+        (Key::plain(KeyCode::Tab), Edit),
+        (Key::ctrl('c'), Abort),
+        (Key::plain(KeyCode::Esc), Cancel),
+        (Key::char('q'), Cancel),
+        (Key::char('k'), Up),
+        (Key::char('j'), Down),
+        (Key::plain(KeyCode::Up), Up),
+        (Key::plain(KeyCode::Down), Down),
+        (Key::ctrl('b'), PageUp),
+        (Key::ctrl('f'), PageDown),
+        (Key::plain(KeyCode::PageUp), PageUp),
+        (Key::plain(KeyCode::PageDown), PageDown),
+        (Key::char('g'), Top),
+        (Key::char('G'), Bottom),
+        (Key::plain(KeyCode::F(1)), ScopeGlobal),
+        (Key::plain(KeyCode::F(2)), ScopeSession),
+        (Key::plain(KeyCode::F(3)), ScopeLocal),
+        (Key::plain(KeyCode::F(4)), ScopeHost),
+        (Key::plain(KeyCode::F(5)), ScopeProject),
+        (Key::char('s'), NextScope),
+        (Key::plain(KeyCode::BackTab), NextScope),
+        (Key::char('m'), CycleMode),
+        (Key::ctrl('r'), CycleMode),
+        (Key::char('u'), ToggleDedup),
+        (Key::ctrl('t'), ToggleDedup),
+        (Key::char('o'), ToggleRuns),
+        (Key::ctrl('o'), ToggleRuns),
+        (Key::char('p'), TogglePreview),
+        (Key::plain(KeyCode::F(6)), TogglePreview),
+        (Key::char(' '), ToggleMark),
+        (Key::ctrl(' '), ToggleMark),
+        (Key::char('V'), ToggleMarkAll),
+        (Key::char('d'), DeleteEntry),
+        (Key::char('e'), Export),
+        (Key::ctrl('s'), Export),
+        (Key::char('h'), BackwardChar),
+        (Key::char('l'), ForwardChar),
+        (Key::plain(KeyCode::Left), BackwardChar),
+        (Key::plain(KeyCode::Right), ForwardChar),
+        (Key::char('0'), BeginningOfLine),
+        (Key::char('$'), EndOfLine),
+        (Key::plain(KeyCode::Home), BeginningOfLine),
+        (Key::plain(KeyCode::End), EndOfLine),
+        (Key::char('b'), BackwardWord),
+        (Key::char('w'), ForwardWord),
+        (Key::char('x'), DeleteChar),
+        (Key::char('X'), BackwardDeleteChar),
+        (Key::char('i'), ViInsert),
+        (Key::char('a'), ViAppend),
+        (Key::char('I'), ViInsertBol),
+        (Key::char('A'), ViAppendEol),
+    ]
+}
+
+/// What the header's help line mentions, in order.
+const HELP: &[(Action, &str)] = &[
+    (Action::CycleMode, "Mode"),
+    (Action::ToggleDedup, "Dedup"),
+    (Action::ToggleRuns, "Runs"),
+    (Action::TogglePreview, "Preview"),
+    (Action::ToggleMark, "Mark"),
+    (Action::Accept, "Run"),
+    (Action::Edit, "Edit"),
+];
+
+const SCOPE_ACTIONS: [Action; 5] = [
+    Action::ScopeGlobal,
+    Action::ScopeSession,
+    Action::ScopeLocal,
+    Action::ScopeHost,
+    Action::ScopeProject,
+];
+
+impl Keymap {
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Emacs => Self {
+                insert: common_bindings(),
+                normal: None,
+            },
+            Preset::Vi => {
+                let mut insert = common_bindings();
+                bind(&mut insert, Key::plain(KeyCode::Esc), Some(Action::ViNormal));
+                Self {
+                    insert,
+                    normal: Some(vi_normal_bindings()),
+                }
+            }
+        }
+    }
+
+    /// Start from `preset` and apply config overrides: key names mapped to
+    /// action names, or to `"none"` to unbind the key.
+    pub fn new(
+        preset: Preset,
+        bindings: &BTreeMap<String, String>,
+        normal: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let mut keymap = Self::preset(preset);
+        apply(&mut keymap.insert, bindings)?;
+        match &mut keymap.normal {
+            Some(bindings) => apply(bindings, normal)?,
+            None if !normal.is_empty() => bail!("normal-mode bindings need the vi preset"),
+            None => {}
+        }
+        Ok(keymap)
+    }
+
+    pub fn is_modal(&self) -> bool {
+        self.normal.is_some()
+    }
+
+    fn bindings(&self, normal_mode: bool) -> &Bindings {
+        match &self.normal {
+            Some(normal) if normal_mode => normal,
+            _ => &self.insert,
+        }
+    }
+
+    pub fn action(&self, normal_mode: bool, key: KeyEvent) -> Option<Action> {
+        let key = Key::from(key);
+        self.bindings(normal_mode)
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|(_, action)| *action)
+    }
+
+    fn first_key(&self, normal_mode: bool, action: Action) -> Option<Key> {
+        self.bindings(normal_mode)
+            .iter()
+            .find(|(_, bound)| *bound == action)
+            .map(|(key, _)| *key)
+    }
+
+    /// Help for the header, e.g. `F1-F5/S-Tab: Scope | ^R: Mode | ...`,
+    /// naming the first key bound to each action.
+    pub fn help(&self, normal_mode: bool) -> String {
+        let mut parts = Vec::new();
+
+        // Scope keys in a row like F1..F5 are shown as a range
+        let mut scope_keys = Vec::new();
+        let direct: Vec<Option<Key>> = SCOPE_ACTIONS
+            .iter()
+            .map(|&action| self.first_key(normal_mode, action))
+            .collect();
+        if let [Some(first), .., Some(last)] = direct[..] {
+            let in_a_row = direct.iter().enumerate().all(|(i, key)| match (key, first.code) {
+                (Some(Key { code: KeyCode::F(n), .. }), KeyCode::F(start)) => *n as usize == start as usize + i,
+                _ => false,
+            });
+            if in_a_row {
+                scope_keys.push(format!("{}-{}", first, last));
+            }
+        }
+        if let Some(key) = self.first_key(normal_mode, Action::NextScope) {
+            scope_keys.push(key.to_string());
+        }
+        if !scope_keys.is_empty() {
+            parts.push(format!("{}: Scope", scope_keys.join("/")));
+        }
+
+        for &(action, label) in HELP {
+            if let Some(key) = self.first_key(normal_mode, action) {
+                parts.push(format!("{}: {}", key, label));
+            }
+        }
+        if let (Some(_), true) = (&self.normal, !normal_mode) {
+            if let Some(key) = self.first_key(false, Action::ViNormal) {
+                parts.push(format!("{}: Normal", key));
+            }
+        }
+        parts.join(" | ")
+    }
+}
+
+/// Rebind `key`, keeping its place in the list, or drop it for `None`.
+fn bind(bindings: &mut Bindings, key: Key, action: Option<Action>) {
+    match (bindings.iter().position(|(bound, _)| *bound == key), action) {
+        (Some(index), Some(action)) => bindings[index].1 = action,
+        (Some(index), None) => {
+            bindings.remove(index);
+        }
+        (None, Some(action)) => bindings.push((key, action)),
+        (None, None) => {}
+    }
+}
+
+fn apply(bindings: &mut Bindings, overrides: &BTreeMap<String, String>) -> Result<()> {
+    for (key, action) in overrides {
+        let key: Key = key.parse()?;
+        let action = match action.as_str() {
+            "none" => None,
+            name => Some(name.parse()?),
+        };
+        bind(bindings, key, action);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn key_names_round_trip_through_events() {
+        let parse = |name: &str| name.parse::<Key>().unwrap();
+        assert_eq!(parse("ctrl-r"), Key::from(event(KeyCode::Char('r'), KeyModifiers::CONTROL)));
+        assert_eq!(parse("Ctrl-Space"), Key::from(event(KeyCode::Char(' '), KeyModifiers::CONTROL)));
+        assert_eq!(parse("shift-tab"), Key::from(event(KeyCode::BackTab, KeyModifiers::SHIFT)));
+        assert_eq!(parse("G"), Key::from(event(KeyCode::Char('G'), KeyModifiers::SHIFT)));
+        assert_eq!(parse("-"), Key::char('-'));
+        assert_eq!(parse("alt--"), Key::alt('-'));
+        assert_eq!(parse("f12"), Key::plain(KeyCode::F(12)));
+        assert!("f13".parse::<Key>().is_err());
+        assert!("hyper-x".parse::<Key>().is_err());
+        assert!("shift-a".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn overrides_rebind_unbind_and_show_in_help() {
+        let bindings = BTreeMap::from([
+            ("alt-m".to_string(), "toggle-mark-all".to_string()),
+            ("f1".to_string(), "none".to_string()),
+            ("ctrl-g".to_string(), "toggle-mode".to_string()),
+        ]);
+        assert!(Keymap::new(Preset::Emacs, &bindings, &BTreeMap::new()).is_err());
+
+        let bindings = BTreeMap::from([
+            ("alt-m".to_string(), "toggle-mark-all".to_string()),
+            ("f1".to_string(), "none".to_string()),
+        ]);
+        let keymap = Keymap::new(Preset::Emacs, &bindings, &BTreeMap::new()).unwrap();
+        let alt = |c| event(KeyCode::Char(c), KeyModifiers::ALT);
+        assert_eq!(keymap.action(false, alt('m')), Some(Action::ToggleMarkAll));
+        assert_eq!(keymap.action(false, alt('a')), Some(Action::ToggleMarkAll));
+        assert_eq!(keymap.action(false, event(KeyCode::F(1), KeyModifiers::NONE)), None);
+        assert!(keymap.help(false).starts_with("S-Tab: Scope | ^R: Mode"));
+
+        assert_eq!(
+            Keymap::default().help(false),
+            "F1-F5/S-Tab: Scope | ^R: Mode | ^T: Dedup | ^O: Runs | F6: Preview | ^Space: Mark | Enter: Run | Tab: Edit"
+        );
+    }
+
+    #[test]
+    fn ctrl_a_moves_to_the_start_like_readline() {
+        let keymap = Keymap::default();
+        let ctrl_a = event(KeyCode::Char('a'), KeyModifiers::CONTROL);
+        let alt_a = event(KeyCode::Char('a'), KeyModifiers::ALT);
+        assert_eq!(keymap.action(false, ctrl_a), Some(Action::BeginningOfLine));
+        assert_eq!(keymap.action(false, alt_a), Some(Action::ToggleMarkAll));
+        assert_eq!(Keymap::preset(Preset::Vi).action(false, ctrl_a), Some(Action::BeginningOfLine));
+    }
+
+    #[test]
+    fn vi_preset_is_modal() {
+        let keymap = Keymap::preset(Preset::Vi);
+        let esc = event(KeyCode::Esc, KeyModifiers::NONE);
+        let j = event(KeyCode::Char('j'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(false, esc), Some(Action::ViNormal));
+        assert_eq!(keymap.action(false, j), None);
+        assert_eq!(keymap.action(true, j), Some(Action::Down));
+        assert_eq!(keymap.action(true, esc), Some(Action::Cancel));
+        assert!(keymap.help(true).starts_with("F1-F5/s: Scope | m: Mode"));
+
+        let normal = BTreeMap::from([("x".to_string(), "delete-entry".to_string())]);
+        assert!(Keymap::new(Preset::Emacs, &BTreeMap::new(), &normal).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A single-line text buffer with a cursor, edited with readline-style
+/// commands.
+///
+/// AIDEV-NOTE: the cursor is a byte offset that always sits on a grapheme
+/// boundary, so moving over accented letters or emoji takes one keypress.
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    text: String,
+    cursor: usize,
+}
+
+impl LineEditor {
+    /// Start with `text`, flattened like a paste, and the cursor at its end.
+    pub fn new(text: String) -> Self {
+        let mut editor = Self::default();
+        editor.insert_str(&text);
+        editor
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Columns between the start of the text and the cursor.
+    pub fn cursor_width(&self) -> usize {
+        self.text[..self.cursor].width()
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.cursor == self.text.len()
+    }
+
+    /// Text before the cursor.
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Insert pasted text; line breaks become spaces since the query is one line.
+    pub fn insert_str(&mut self, text: &str) {
+        let flattened: String = text
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        self.text.insert_str(self.cursor, &flattened);
+        self.cursor += flattened.len();
+    }
+
+    /// Delete the grapheme before the cursor (Backspace).
+    pub fn delete_back(&mut self) {
+        let start = self.prev_boundary();
+        self.remove(start, self.cursor);
+    }
+
+    /// Delete the grapheme under the cursor (Delete).
+    pub fn delete_forward(&mut self) {
+        let end = self.next_boundary();
+        self.remove(self.cursor, end);
+    }
+
+    /// Delete back to the previous whitespace (Ctrl+W).
+    pub fn delete_word_back(&mut self) {
+        let before = self.before_cursor().trim_end_matches(char::is_whitespace);
+        let start = before
+            .rfind(char::is_whitespace)
+            .map_or(0, |index| index + before[index..].chars().next().map_or(1, char::len_utf8));
+        self.remove(start, self.cursor);
+    }
+
+    /// Delete everything before the cursor (Ctrl+U).
+    pub fn kill_to_start(&mut self) {
+        self.remove(0, self.cursor);
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.prev_boundary();
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Move to the start of the previous word (Alt+B).
+    pub fn move_word_left(&mut self) {
+        self.cursor = self
+            .text
+            .unicode_word_indices()
+            .map(|(index, _)| index)
+            .take_while(|&index| index < self.cursor)
+            .last()
+            .unwrap_or(0);
+    }
+
+    /// Move past the end of the next word (Alt+F).
+    pub fn move_word_right(&mut self) {
+        self.cursor = self
+            .text
+            .unicode_word_indices()
+            .map(|(index, word)| index + word.len())
+            .find(|&end| end > self.cursor)
+            .unwrap_or(self.text.len());
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+
+    /// Remove `start..end`, leaving the cursor at `start`.
+    fn remove(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_and_deletes_at_the_cursor() {
+        let mut editor = LineEditor::new("gt status".to_string());
+        editor.move_home();
+        editor.move_right();
+        editor.insert_char('i');
+        assert_eq!(editor.text(), "git status");
+
+        editor.move_end();
+        editor.delete_back();
+        editor.move_home();
+        editor.delete_forward();
+        assert_eq!(editor.text(), "it statu");
+        editor.delete_back();
+        assert_eq!(editor.text(), "it statu");
+    }
+
+    #[test]
+    fn moves_over_whole_graphemes() {
+        let mut editor = LineEditor::new("cafe\u{301} 日本 🚀".to_string());
+        editor.move_left();
+        editor.move_left();
+        assert_eq!(editor.before_cursor(), "cafe\u{301} 日本");
+        assert_eq!(editor.cursor_width(), 9);
+
+        editor.delete_back();
+        assert_eq!(editor.text(), "cafe\u{301} 日 🚀");
+
+        editor.move_home();
+        for _ in 0..3 {
+            editor.move_right();
+        }
+        editor.delete_forward();
+        assert_eq!(editor.text(), "caf 日 🚀");
+    }
+
+    #[test]
+    fn word_commands_follow_readline() {
+        let mut editor = LineEditor::new("git log --oneline  ".to_string());
+        editor.delete_word_back();
+        assert_eq!(editor.text(), "git log ");
+
+        editor.move_word_left();
+        assert_eq!(editor.before_cursor(), "git ");
+        editor.move_word_left();
+        assert_eq!(editor.before_cursor(), "");
+        editor.move_word_right();
+        assert_eq!(editor.before_cursor(), "git");
+
+        editor.move_word_right();
+        editor.kill_to_start();
+        assert_eq!(editor.text(), " ");
+    }
+
+    #[test]
+    fn paste_is_flattened_to_one_line() {
+        let mut editor = LineEditor::new("echo ".to_string());
+        editor.insert_str("a\nb\tc");
+        assert_eq!(editor.text(), "echo a b c");
+        assert_eq!(editor.cursor_width(), 10);
+
+        // So is a multi-line --prefix
+        let editor = LineEditor::new("for f in *; do\n  echo $f\ndone".to_string());
+        assert_eq!(editor.text(), "for f in *; do   echo $f done");
+        assert!(editor.at_end());
+    }
+}
@@ -1,11 +1,29 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand};
 use anyhow::Result;
+use std::io::Write;
 
+mod config;
+mod duration;
+mod export;
 mod history;
+mod import;
+mod keymap;
+mod line_editor;
+mod matcher;
+mod project;
+mod query;
+mod ranking;
 mod search;
 mod storage;
+mod theme;
 
+use config::Config;
+use export::ExportFormat;
 use history::{HistoryManager, SearchScope};
+use import::ImportSource;
+use matcher::MatchMode;
+use storage::CompactPolicy;
+use search::{format_relative_time, Height, JoinStyle};
 
 #[derive(Parser)]
 #[command(name = "rehash")]
@@ -19,6 +37,9 @@ struct Args {
     /// Additional read-only database files (comma-separated)
     #[arg(long, global = true, value_delimiter = ',')]
     read_sources: Vec<String>,
+    /// Ranking weight overrides, e.g. "match=1,frequency=0.5,recency=0.8,success=0.3,proximity=0.4,half-life=168"
+    #[arg(long, global = true)]
+    rank_weights: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -31,64 +52,263 @@ enum Commands {
         #[arg(short, long, default_value = "0")]
         exit_code: i32,
     },
+    /// Record that a command is starting; prints an id for `end` (shell preexec)
+    Start {
+        /// The command about to run
+        command: String,
+    },
+    /// Finish a command recorded with `start` (shell precmd)
+    End {
+        /// Id printed by `start`
+        id: String,
+        /// Exit code of the command
+        #[arg(short, long, default_value = "0")]
+        exit_code: i32,
+    },
     /// Search history with fuzzy matching
     Search {
-        /// Search query
+        /// Search query; may include field filters such as dir:~/src, exit:!0,
+        /// host:laptop, session:current, after:2d, before:2025-01-01, dur:>30s
         query: Option<String>,
-        /// Search scope: global, session, or local
-        #[arg(short, long, value_enum, default_value = "global")]
-        scope: SearchScope,
-        /// Maximum number of results
-        #[arg(short, long, default_value = "20")]
-        max_results: usize,
+        /// Search scope: global, session, local, host or project [default: global]
+        #[arg(short, long, value_enum)]
+        scope: Option<SearchScope>,
+        /// How the query text is matched [default: fuzzy]
+        #[arg(long, value_enum)]
+        mode: Option<MatchMode>,
+        /// Maximum number of results [default: 20]
+        #[arg(short, long)]
+        max_results: Option<usize>,
+        /// List every run instead of collapsing identical commands
+        #[arg(long)]
+        no_dedup: bool,
+    },
+    /// Write history out in another format, filtered like `search`
+    Export {
+        /// Search query, with the same field filters as `search`
+        query: Option<String>,
+        /// Search scope: global, session, local, host or project [default: global]
+        #[arg(short, long, value_enum)]
+        scope: Option<SearchScope>,
+        /// How the query text is matched [default: fuzzy]
+        #[arg(long, value_enum)]
+        mode: Option<MatchMode>,
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "jsonl")]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
     },
     /// Interactive fuzzy search
     Interactive {
-        /// Initial search scope: global, session, or local
-        #[arg(short, long, value_enum, default_value = "global")]
-        scope: SearchScope,
+        /// Initial search scope: global, session, local, host or project [default: global]
+        #[arg(short, long, value_enum)]
+        scope: Option<SearchScope>,
+        /// Initial match mode (Ctrl+R cycles modes in the UI) [default: fuzzy]
+        #[arg(long, value_enum)]
+        mode: Option<MatchMode>,
         /// Prefill the search query with this text
         #[arg(short, long)]
         prefix: Option<String>,
-        /// Write result to file instead of stdout (for shell integration)
+        /// Start with every run listed instead of one row per command (Ctrl+T toggles)
+        #[arg(long)]
+        no_dedup: bool,
+        /// How several marked commands are combined: one per line, or with && [default: newline]
+        #[arg(long, value_enum)]
+        join: Option<JoinStyle>,
+        /// Draw the picker below the cursor instead of taking over the screen
+        #[arg(long)]
+        inline: bool,
+        /// Lines (20) or percentage of the terminal (40%) for the inline picker; implies
+        /// --inline, while `full` or 100% takes over the screen
+        #[arg(long, value_parser = Height::parse)]
+        height: Option<Height>,
+        /// Write the result to a file instead of stdout (for shell integration):
+        /// the action (`execute` or `edit`) on the first line, then the command
         #[arg(long)]
         output_file: Option<String>,
     },
     /// Show statistics
     Stats,
+    /// Shrink the history database
+    Compact {
+        /// Keep only the most recent N entries
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Drop entries older than this (e.g. 90d, 12w, 1y)
+        #[arg(long, value_parser = duration::parse_duration)]
+        older_than: Option<chrono::Duration>,
+        /// Collapse identical commands per directory, keeping the newest
+        #[arg(long)]
+        dedup: bool,
+        /// Report what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Copy existing history files into the --database store
+    Migrate {
+        /// Source files or database URLs (defaults to the standard history.jsonl)
+        sources: Vec<String>,
+    },
+    /// Import another shell's history into the --database store
+    Import {
+        /// Where the history comes from
+        #[arg(value_enum)]
+        source: ImportSource,
+        /// History file or database [default: the source's usual location]
+        path: Option<std::path::PathBuf>,
+    },
     /// Clear history
     Clear {
-        /// Clear scope: global, session, or local
+        /// Clear scope: global, session, local, host or project
         #[arg(short, long, value_enum, default_value = "global")]
         scope: SearchScope,
     },
+    /// Inspect the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration: the file's settings with defaults
+    /// filled in and global flags applied
+    Show,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let mut history_manager = HistoryManager::new(args.database, args.read_sources)?;
+
+    // AIDEV-NOTE: global flags are folded into the config so `config show`
+    // reports what this invocation actually uses
+    // AIDEV-NOTE: the shell hooks run add/start/end at every prompt; a typo in
+    // the config must not stop history being recorded, so those warn once
+    // (end stays quiet, start already warned) and carry on with the defaults
+    let recording = matches!(
+        args.command,
+        Some(Commands::Add { .. } | Commands::Start { .. } | Commands::End { .. })
+    );
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(err) if recording => {
+            if !matches!(args.command, Some(Commands::End { .. })) {
+                eprintln!("rehash: recording with the default settings. {:#}", err);
+            }
+            Config::default()
+        }
+        Err(err) => return Err(err),
+    };
+    if let Some(database) = args.database {
+        config.storage.database = Some(database);
+    }
+    if !args.read_sources.is_empty() {
+        config.storage.read_sources = args.read_sources;
+    }
+    if let Some(overrides) = &args.rank_weights {
+        config.ranking = config.ranking.with_overrides(overrides)?;
+    }
+
+    if let Some(Commands::Config { command: ConfigCommand::Show }) = args.command {
+        config.ui.theme = Some(config.theme_name().to_string());
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    let mut history_manager =
+        HistoryManager::new(config.storage.database.clone(), config.storage.read_sources.clone())?;
+    history_manager.set_ranking_weights(config.ranking);
+    history_manager.set_ignore_patterns(config.ignore_patterns()?);
 
     match args.command {
         Some(Commands::Add { command, exit_code }) => {
             history_manager.add_command(&command, exit_code)?;
         }
-        Some(Commands::Search { query, scope, max_results }) => {
-            let results = if let Some(q) = query {
-                history_manager.search(&q, scope, max_results)?
+        Some(Commands::Start { command }) => {
+            if let Some(id) = history_manager.start_command(&command)? {
+                println!("{}", id);
+            }
+        }
+        Some(Commands::End { id, exit_code }) => {
+            history_manager.end_command(&id, exit_code)?;
+        }
+        Some(Commands::Search { query, scope, mode, max_results, no_dedup }) => {
+            let results = history_manager.search(
+                query.as_deref().unwrap_or(""),
+                scope.unwrap_or(config.search.scope),
+                mode.unwrap_or(config.search.mode),
+                config.search.dedup && !no_dedup,
+                max_results.unwrap_or(config.search.max_results),
+            )?;
+
+            // AIDEV-NOTE: plain commands when piped, so scripts can consume them
+            if atty::is(atty::Stream::Stdout) {
+                for entry in results {
+                    let runs = if entry.count > 1 { format!("{}x", entry.count) } else { String::new() };
+                    let exit = if entry.exit_code != 0 { entry.exit_code.to_string() } else { String::new() };
+                    println!(
+                        "{:>8} {:>6} {:>4}  {}",
+                        format_relative_time(&entry.timestamp),
+                        runs,
+                        exit,
+                        entry.command
+                    );
+                }
             } else {
-                history_manager.list_recent(scope, max_results)?
-            };
-            
-            for entry in results {
-                println!("{}", entry.command);
+                for entry in results {
+                    println!("{}", entry.command);
+                }
             }
         }
-        Some(Commands::Interactive { scope, prefix, output_file }) => {
-            if let Some(selected) = history_manager.interactive_search_with_prefix(scope, prefix)? {
+        Some(Commands::Export { query, scope, mode, format, output }) => {
+            let entries = history_manager.export(
+                query.as_deref().unwrap_or(""),
+                scope.unwrap_or(config.search.scope),
+                mode.unwrap_or(config.search.mode),
+            )?;
+            match output {
+                Some(path) => {
+                    let mut file = std::io::BufWriter::new(export::create_output(&path)?);
+                    export::write_entries(format, &entries, &mut file)?;
+                    file.flush()?;
+                }
+                None => {
+                    // AIDEV-NOTE: `rehash export | head` closing the pipe early is not an error
+                    let written = export::write_entries(format, &entries, &mut std::io::stdout().lock());
+                    if let Err(err) = written {
+                        let broken_pipe = err
+                            .downcast_ref::<std::io::Error>()
+                            .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe);
+                        if !broken_pipe {
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+        }
+        Some(Commands::Interactive { scope, mode, prefix, no_dedup, join, inline, height, output_file }) => {
+            let mut options = config.picker_options()?;
+            if let Some(join) = join {
+                options.join = join;
+            }
+            if let Some(height) = height {
+                options.height = height;
+            } else if inline && options.height == Height::Full {
+                options.height = Height::INLINE;
+            }
+            if let Some(selected) = history_manager.interactive_search_with_prefix(
+                scope.unwrap_or(config.search.scope),
+                mode.unwrap_or(config.search.mode),
+                config.search.dedup && !no_dedup,
+                options,
+                prefix,
+            )? {
                 if let Some(file_path) = output_file {
-                    std::fs::write(file_path, selected)?;
+                    std::fs::write(file_path, selected.to_output_file())?;
                 } else {
-                    print!("{}", selected);
+                    print!("{}", selected.command);
                 }
             }
         }
@@ -98,14 +318,48 @@ fn main() -> Result<()> {
             println!("Unique commands: {}", stats.unique_commands);
             println!("Directory-local commands: {}", stats.local_commands);
         }
+        Some(Commands::Compact { keep, older_than, dedup, dry_run }) => {
+            let policy = CompactPolicy { keep, older_than, dedup };
+            if policy.is_empty() {
+                anyhow::bail!("Nothing to do: pass --keep, --older-than and/or --dedup");
+            }
+
+            let report = history_manager.compact(&policy, dry_run)?;
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            println!("{} {} of {} entries", verb, report.removed(), report.before);
+            if report.expired > 0 {
+                println!("  {} older than cutoff", report.expired);
+            }
+            if report.duplicates > 0 {
+                println!("  {} duplicates", report.duplicates);
+            }
+            if report.over_limit > 0 {
+                println!("  {} beyond the most recent {}", report.over_limit, keep.unwrap_or(0));
+            }
+        }
+        Some(Commands::Migrate { sources }) => {
+            let (read, added) = history_manager.migrate(sources)?;
+            println!("Migrated {} entries ({} already present)", added, read - added);
+        }
+        Some(Commands::Import { source, path }) => {
+            let (read, added) = history_manager.import(source, path)?;
+            println!("Imported {} entries ({} already present)", added, read - added);
+        }
         Some(Commands::Clear { scope }) => {
             history_manager.clear_history(scope)?;
             println!("History cleared");
         }
+        Some(Commands::Config { .. }) => unreachable!("handled before opening the history"),
         None => {
             // Default to interactive search
-            if let Some(selected) = history_manager.interactive_search_with_prefix(SearchScope::Global, None)? {
-                println!("{}", selected);
+            if let Some(selected) = history_manager.interactive_search_with_prefix(
+                config.search.scope,
+                config.search.mode,
+                config.search.dedup,
+                config.picker_options()?,
+                None,
+            )? {
+                println!("{}", selected.command);
             }
         }
     }
@@ -0,0 +1,402 @@
+use clap::ValueEnum;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How the free text of a query is matched against commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Skim-style fuzzy matching
+    Fuzzy,
+    /// Case-insensitive substring (smart case)
+    Exact,
+    /// Command starts with the text (smart case)
+    Prefix,
+    /// Full regular expression
+    Regex,
+    /// fzf extended syntax: 'exact ^prefix suffix$ !negate, space-separated terms all match
+    Extended,
+}
+
+impl MatchMode {
+    pub fn next(self) -> Self {
+        match self {
+            MatchMode::Fuzzy => MatchMode::Exact,
+            MatchMode::Exact => MatchMode::Prefix,
+            MatchMode::Prefix => MatchMode::Regex,
+            MatchMode::Regex => MatchMode::Extended,
+            MatchMode::Extended => MatchMode::Fuzzy,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MatchMode::Fuzzy => "fuzzy",
+            MatchMode::Exact => "exact",
+            MatchMode::Prefix => "prefix",
+            MatchMode::Regex => "regex",
+            MatchMode::Extended => "extended",
+        }
+    }
+}
+
+/// Query text compiled once for a given mode and then tested against many
+/// commands.
+pub struct Pattern {
+    kind: PatternKind,
+}
+
+enum PatternKind {
+    Fuzzy(String),
+    Substring(Text),
+    Prefix(Text),
+    Regex(Regex),
+    Extended(Vec<Term>),
+}
+
+/// Literal text with smart case: case-insensitive unless it contains uppercase.
+struct Text {
+    needle: String,
+    ignore_case: bool,
+}
+
+impl Text {
+    fn new(text: &str) -> Self {
+        let ignore_case = !text.chars().any(char::is_uppercase);
+        Self {
+            needle: if ignore_case { text.to_lowercase() } else { text.to_string() },
+            ignore_case,
+        }
+    }
+
+    fn haystack<'a>(&self, command: &'a str) -> std::borrow::Cow<'a, str> {
+        if self.ignore_case {
+            command.to_lowercase().into()
+        } else {
+            command.into()
+        }
+    }
+
+    /// Character positions of the first occurrence of the needle in `command`.
+    fn find(&self, command: &str) -> Option<Range<usize>> {
+        let (haystack, origins) = self.fold(command);
+        let position = haystack.find(&self.needle)?;
+        Some(self.span(&haystack, &origins, position))
+    }
+
+    /// Character positions of the needle if `command` starts with it.
+    fn find_prefix(&self, command: &str) -> Option<Range<usize>> {
+        let (haystack, origins) = self.fold(command);
+        haystack
+            .starts_with(&self.needle)
+            .then(|| self.span(&haystack, &origins, 0))
+    }
+
+    /// Character positions of the needle if `command` ends with it.
+    fn find_suffix(&self, command: &str) -> Option<Range<usize>> {
+        let (haystack, origins) = self.fold(command);
+        haystack
+            .ends_with(&self.needle)
+            .then(|| self.span(&haystack, &origins, haystack.len() - self.needle.len()))
+    }
+
+    /// `command` case-folded like the needle, along with the character
+    /// position in `command` each folded character came from.
+    ///
+    /// AIDEV-NOTE: lowercasing can change the number of characters (`İ`
+    /// becomes `i̇`), so positions in the folded text can't be used as they are
+    fn fold(&self, command: &str) -> (String, Vec<usize>) {
+        let mut haystack = String::with_capacity(command.len());
+        let mut origins = Vec::with_capacity(command.len());
+        for (index, c) in command.chars().enumerate() {
+            if self.ignore_case {
+                for lower in c.to_lowercase() {
+                    haystack.push(lower);
+                    origins.push(index);
+                }
+            } else {
+                haystack.push(c);
+                origins.push(index);
+            }
+        }
+        (haystack, origins)
+    }
+
+    /// Positions in the original command of the needle found at byte
+    /// `position` of the folded `haystack`.
+    fn span(&self, haystack: &str, origins: &[usize], position: usize) -> Range<usize> {
+        let start = haystack[..position].chars().count();
+        let matched = &origins[start..start + self.needle.chars().count()];
+        match (matched.first(), matched.last()) {
+            (Some(&first), Some(&last)) => first..last + 1,
+            _ => 0..0,
+        }
+    }
+}
+
+struct Term {
+    kind: TermKind,
+    negate: bool,
+}
+
+enum TermKind {
+    Fuzzy(String),
+    Substring(Text),
+    Prefix(Text),
+    Suffix(Text),
+    Equal(Text),
+}
+
+impl Pattern {
+    pub fn new(mode: MatchMode, text: &str) -> Self {
+        let kind = match mode {
+            MatchMode::Fuzzy => PatternKind::Fuzzy(text.to_string()),
+            MatchMode::Exact => PatternKind::Substring(Text::new(text)),
+            MatchMode::Prefix => PatternKind::Prefix(Text::new(text)),
+            // AIDEV-NOTE: a half-typed regex like `foo(` is matched literally
+            // rather than emptying the result list
+            MatchMode::Regex => RegexBuilder::new(text)
+                .case_insensitive(!text.chars().any(char::is_uppercase))
+                .build()
+                .map(PatternKind::Regex)
+                .unwrap_or_else(|_| PatternKind::Substring(Text::new(text))),
+            MatchMode::Extended => {
+                PatternKind::Extended(text.split_whitespace().filter_map(Term::parse).collect())
+            }
+        };
+        Self { kind }
+    }
+
+    /// Score `command`, higher is better; `None` if it doesn't match.
+    pub fn score(&self, matcher: &SkimMatcherV2, command: &str) -> Option<i64> {
+        match &self.kind {
+            PatternKind::Fuzzy(text) => matcher.fuzzy_match(command, text),
+            PatternKind::Substring(text) => {
+                let position = text.haystack(command).find(&text.needle)?;
+                // Earlier and tighter matches rank higher
+                Some(-(position as i64) - command.len() as i64 / 8)
+            }
+            PatternKind::Prefix(text) => text
+                .haystack(command)
+                .starts_with(&text.needle)
+                .then(|| -(command.len() as i64)),
+            PatternKind::Regex(regex) => regex
+                .find(command)
+                .map(|found| -(found.start() as i64) - command.len() as i64 / 8),
+            PatternKind::Extended(terms) => terms.iter().try_fold(0, |total, term| {
+                term.score(matcher, command).map(|score| total + score)
+            }),
+        }
+    }
+
+    /// Character positions in `command` that the pattern matched, in order,
+    /// for highlighting. Empty if it doesn't match.
+    pub fn indices(&self, matcher: &SkimMatcherV2, command: &str) -> Vec<usize> {
+        let mut indices: Vec<usize> = match &self.kind {
+            PatternKind::Fuzzy(text) => matcher
+                .fuzzy_indices(command, text)
+                .map(|(_, indices)| indices)
+                .unwrap_or_default(),
+            PatternKind::Substring(text) => text.find(command).into_iter().flatten().collect(),
+            PatternKind::Prefix(text) => text.find_prefix(command).into_iter().flatten().collect(),
+            PatternKind::Regex(regex) => regex
+                .find(command)
+                .map(|found| {
+                    let start = command[..found.start()].chars().count();
+                    (start..start + found.as_str().chars().count()).collect()
+                })
+                .unwrap_or_default(),
+            PatternKind::Extended(terms) => {
+                if self.score(matcher, command).is_none() {
+                    return Vec::new();
+                }
+                terms
+                    .iter()
+                    .filter(|term| !term.negate)
+                    .flat_map(|term| term.indices(matcher, command))
+                    .collect()
+            }
+        };
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+}
+
+impl Term {
+    fn parse(token: &str) -> Option<Self> {
+        let (negate, token) = match token.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let kind = if let Some(rest) = token.strip_prefix('\'') {
+            TermKind::Substring(Text::new(rest))
+        } else if let Some(rest) = token.strip_prefix('^') {
+            match rest.strip_suffix('$') {
+                Some(inner) => TermKind::Equal(Text::new(inner)),
+                None => TermKind::Prefix(Text::new(rest)),
+            }
+        } else if let Some(rest) = token.strip_suffix('$') {
+            TermKind::Suffix(Text::new(rest))
+        } else if negate {
+            // fzf treats negated plain terms as exact, not fuzzy
+            TermKind::Substring(Text::new(token))
+        } else {
+            TermKind::Fuzzy(token.to_string())
+        };
+
+        // A lone `!` or `'` while typing shouldn't filter anything yet
+        match &kind {
+            TermKind::Fuzzy(text) if text.is_empty() => None,
+            TermKind::Substring(text)
+            | TermKind::Prefix(text)
+            | TermKind::Suffix(text)
+            | TermKind::Equal(text)
+                if text.needle.is_empty() =>
+            {
+                None
+            }
+            _ => Some(Self { kind, negate }),
+        }
+    }
+
+    fn score(&self, matcher: &SkimMatcherV2, command: &str) -> Option<i64> {
+        let score = match &self.kind {
+            TermKind::Fuzzy(text) => matcher.fuzzy_match(command, text),
+            TermKind::Substring(text) => text.haystack(command).contains(&text.needle).then_some(0),
+            TermKind::Prefix(text) => text.haystack(command).starts_with(&text.needle).then_some(0),
+            TermKind::Suffix(text) => text.haystack(command).ends_with(&text.needle).then_some(0),
+            TermKind::Equal(text) => (text.haystack(command) == text.needle).then_some(0),
+        };
+
+        if self.negate {
+            match score {
+                Some(_) => None,
+                None => Some(0),
+            }
+        } else {
+            score
+        }
+    }
+
+    fn indices(&self, matcher: &SkimMatcherV2, command: &str) -> Vec<usize> {
+        match &self.kind {
+            TermKind::Fuzzy(text) => matcher
+                .fuzzy_indices(command, text)
+                .map(|(_, indices)| indices)
+                .unwrap_or_default(),
+            TermKind::Substring(text) => text.find(command).into_iter().flatten().collect(),
+            TermKind::Prefix(text) => text.find_prefix(command).into_iter().flatten().collect(),
+            TermKind::Suffix(text) => text.find_suffix(command).into_iter().flatten().collect(),
+            TermKind::Equal(_) => (0..command.chars().count()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(mode: MatchMode, query: &str, command: &str) -> bool {
+        Pattern::new(mode, query).score(&SkimMatcherV2::default(), command).is_some()
+    }
+
+    fn indices(mode: MatchMode, query: &str, command: &str) -> Vec<usize> {
+        Pattern::new(mode, query).indices(&SkimMatcherV2::default(), command)
+    }
+
+    #[test]
+    fn modes_match_as_described() {
+        assert!(matches(MatchMode::Fuzzy, "gst", "git status"));
+        assert!(!matches(MatchMode::Exact, "gst", "git status"));
+        assert!(matches(MatchMode::Exact, "t st", "git status"));
+        assert!(matches(MatchMode::Prefix, "git s", "git status"));
+        assert!(!matches(MatchMode::Prefix, "status", "git status"));
+        assert!(matches(MatchMode::Regex, r"^git (status|log)$", "git log"));
+        assert!(!matches(MatchMode::Regex, r"^git (status|log)$", "git log -p"));
+    }
+
+    #[test]
+    fn uppercase_makes_matching_case_sensitive() {
+        for mode in [MatchMode::Exact, MatchMode::Prefix, MatchMode::Regex, MatchMode::Extended] {
+            assert!(matches(mode, "make", "MAKE test"), "{:?}", mode);
+            assert!(matches(mode, "MAKE", "MAKE test"), "{:?}", mode);
+            assert!(!matches(mode, "Make", "MAKE test"), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn invalid_regex_matches_literally() {
+        assert!(matches(MatchMode::Regex, "echo $(", "echo $(date)"));
+        assert!(!matches(MatchMode::Regex, "echo $(", "echo date"));
+        assert_eq!(indices(MatchMode::Regex, "f(", "if(x)"), [1, 2]);
+    }
+
+    #[test]
+    fn extended_terms() {
+        let command = "cargo build --release";
+        for (query, expected) in [
+            ("crg bld", true),
+            ("crg xyz", false),
+            ("'build", true),
+            ("'bld", false),
+            ("^cargo", true),
+            ("^build", false),
+            ("release$", true),
+            ("cargo$", false),
+            // Spaces split terms: prefix, fuzzy and suffix all hold
+            ("^cargo build --release$", true),
+            ("^cargo$", false),
+            ("!test", true),
+            ("!build", false),
+            ("!^build cargo", true),
+            ("!release$", false),
+            // Bare `!` and `'` while typing don't filter yet
+            ("cargo ! '", true),
+        ] {
+            assert_eq!(matches(MatchMode::Extended, query, command), expected, "{}", query);
+        }
+        assert!(matches(MatchMode::Extended, "^ls$", "ls"));
+        assert!(!matches(MatchMode::Extended, "^ls$", "ls -la"));
+    }
+
+    #[test]
+    fn modes_cycle_through_all() {
+        let mut mode = MatchMode::Fuzzy;
+        let mut labels = Vec::new();
+        for _ in 0..5 {
+            labels.push(mode.label());
+            mode = mode.next();
+        }
+        assert_eq!(mode, MatchMode::Fuzzy);
+        assert_eq!(labels, ["fuzzy", "exact", "prefix", "regex", "extended"]);
+    }
+
+    #[test]
+    fn indices_are_character_positions_in_the_command() {
+        assert_eq!(indices(MatchMode::Exact, "git", "LOG GIT"), [4, 5, 6]);
+        assert_eq!(indices(MatchMode::Exact, "ü", "grüß ü"), [2]);
+        assert_eq!(indices(MatchMode::Prefix, "ls", "LS -la"), [0, 1]);
+        assert_eq!(indices(MatchMode::Regex, "g.t", "é git"), [2, 3, 4]);
+        assert_eq!(indices(MatchMode::Fuzzy, "gst", "git status"), [0, 4, 5]);
+        assert_eq!(
+            indices(MatchMode::Extended, "^cd 'src !x log$", "cd src && git log"),
+            [0, 1, 3, 4, 5, 14, 15, 16]
+        );
+        assert!(indices(MatchMode::Exact, "git", "make").is_empty());
+        assert!(indices(MatchMode::Extended, "make !test", "make test").is_empty());
+    }
+
+    #[test]
+    fn indices_survive_lowercasing_that_changes_length() {
+        // 'İ' lowercases to two characters, 'i' and a combining dot
+        assert_eq!(indices(MatchMode::Exact, "git", "İİ git"), [3, 4, 5]);
+        assert_eq!(indices(MatchMode::Exact, "i", "xİ"), [1]);
+        assert_eq!(indices(MatchMode::Prefix, "i\u{307}s", "İstanbul"), [0, 1]);
+        assert_eq!(indices(MatchMode::Extended, "git$", "İİ git"), [3, 4, 5]);
+        assert_eq!(indices(MatchMode::Extended, "'git", "cd İ && git"), [8, 9, 10]);
+    }
+}
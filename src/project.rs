@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory entries that mark the root of a project.
+///
+/// AIDEV-NOTE: `.git` may be a file (worktrees, submodules); `.rehash-root`
+/// lets people mark a root in trees without version control.
+const ROOT_MARKERS: &[&str] = &[".git", ".hg", ".jj", ".rehash-root"];
+
+#[derive(Debug, Clone)]
+pub struct ProjectInfo {
+    pub root: String,
+    pub branch: Option<String>,
+}
+
+/// Find the project enclosing `dir` by walking up to the nearest root marker.
+pub fn detect(dir: &Path) -> Option<ProjectInfo> {
+    let root = dir
+        .ancestors()
+        .find(|candidate| ROOT_MARKERS.iter().any(|marker| candidate.join(marker).exists()))?;
+
+    Some(ProjectInfo {
+        root: root.to_string_lossy().to_string(),
+        branch: git_branch(root).or_else(|| hg_branch(root)),
+    })
+}
+
+/// Read the current branch straight from `.git/HEAD` rather than running
+/// `git`, since this happens on every recorded command.
+fn git_branch(root: &Path) -> Option<String> {
+    let git_dir = resolve_git_dir(root)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .to_string(),
+        ),
+        // Detached HEAD: show the abbreviated commit
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+fn resolve_git_dir(root: &Path) -> Option<PathBuf> {
+    let dot_git = root.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+
+    // Worktrees and submodules use a `gitdir: <path>` file instead
+    let contents = fs::read_to_string(&dot_git).ok()?;
+    let git_dir = PathBuf::from(contents.trim().strip_prefix("gitdir:")?.trim());
+    Some(if git_dir.is_absolute() { git_dir } else { root.join(git_dir) })
+}
+
+fn hg_branch(root: &Path) -> Option<String> {
+    if !root.join(".hg").is_dir() {
+        return None;
+    }
+    // Mercurial only writes .hg/branch once a named branch is used
+    let branch = fs::read_to_string(root.join(".hg/branch")).unwrap_or_default();
+    let branch = branch.trim();
+    Some(if branch.is_empty() { "default" } else { branch }.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn nearest_marker_is_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let outer = dir.path().join("outer");
+        let inner = outer.join("vendor/inner");
+        fs::create_dir_all(inner.join("src/deep")).unwrap();
+        fs::create_dir_all(outer.join(".jj")).unwrap();
+        write(&inner.join(".rehash-root"), "");
+
+        let project = detect(&inner.join("src/deep")).unwrap();
+        assert_eq!(project.root, inner.to_string_lossy());
+        assert_eq!(project.branch, None);
+        assert_eq!(detect(&outer.join("vendor")).unwrap().root, outer.to_string_lossy());
+        assert!(detect(dir.path()).is_none());
+    }
+
+    #[test]
+    fn branch_comes_from_git_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let head = dir.path().join(".git/HEAD");
+
+        write(&head, "ref: refs/heads/feature/login\n");
+        assert_eq!(detect(dir.path()).unwrap().branch.as_deref(), Some("feature/login"));
+
+        write(&head, "0123456789abcdef0123456789abcdef01234567\n");
+        assert_eq!(detect(dir.path()).unwrap().branch.as_deref(), Some("0123456"));
+    }
+
+    #[test]
+    fn worktrees_point_at_their_git_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let worktree = dir.path().join("wt");
+        write(&dir.path().join("repo/.git/worktrees/wt/HEAD"), "ref: refs/heads/topic\n");
+        write(&worktree.join(".git"), "gitdir: ../repo/.git/worktrees/wt\n");
+
+        let project = detect(&worktree).unwrap();
+        assert_eq!(project.root, worktree.to_string_lossy());
+        assert_eq!(project.branch.as_deref(), Some("topic"));
+    }
+
+    #[test]
+    fn mercurial_branch_defaults_to_default() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".hg")).unwrap();
+        assert_eq!(detect(dir.path()).unwrap().branch.as_deref(), Some("default"));
+
+        write(&dir.path().join(".hg/branch"), "stable\n");
+        assert_eq!(detect(dir.path()).unwrap().branch.as_deref(), Some("stable"));
+    }
+}
@@ -0,0 +1,191 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone, Utc};
+use std::cmp::Ordering;
+
+use crate::duration::parse_duration;
+use crate::history::{HistoryEntry, ScopeContext};
+use crate::storage::is_within;
+
+/// A search box query: `field:value` filters plus free text to fuzzy-match.
+///
+/// Supported fields:
+/// - `dir:~/src` — directory and its subdirectories (`~`, `.` and relative paths work)
+/// - `exit:0`, `exit:!0` — exit code
+/// - `host:laptop` — hostname
+/// - `session:current` or `session:<id>` — shell session
+/// - `after:2d`, `before:2025-01-01` — relative durations, dates or RFC 3339 times
+/// - `dur:>30s`, `dur:<=2m` — command duration
+///
+/// Any filter can be negated with `!` after the colon (`host:!laptop`).
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Everything that is not a field filter, to be fuzzy-matched
+    pub text: String,
+    filters: Vec<(FieldFilter, bool)>,
+}
+
+#[derive(Debug, Clone)]
+enum FieldFilter {
+    Directory(String),
+    ExitCode(i32),
+    Host(String),
+    Session(String),
+    After(DateTime<Utc>),
+    Before(DateTime<Utc>),
+    Duration(Ordering, bool, Duration),
+}
+
+impl Query {
+    /// Parse `input`, resolving `session:current` and relative `dir:` paths
+    /// against `context`.
+    ///
+    /// AIDEV-NOTE: tokens naming a known field with a value that doesn't parse
+    /// (e.g. a half-typed `after:2` in the TUI) are dropped rather than fuzzy
+    /// matched, so results don't flicker to nothing while typing. Unknown
+    /// fields stay part of the free text.
+    pub fn parse(input: &str, context: &ScopeContext) -> Self {
+        let now = Utc::now();
+        let mut text = Vec::new();
+        let mut filters = Vec::new();
+
+        for token in input.split_whitespace() {
+            let Some((field, value)) = token.split_once(':') else {
+                text.push(token);
+                continue;
+            };
+            if !is_field(field) {
+                text.push(token);
+                continue;
+            }
+
+            let (negate, value) = match value.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, value),
+            };
+            if let Some(filter) = parse_filter(field, value, context, now) {
+                filters.push((filter, negate));
+            }
+        }
+
+        Self {
+            text: text.join(" "),
+            filters,
+        }
+    }
+
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.filters
+            .iter()
+            .all(|(filter, negate)| filter.matches(entry) != *negate)
+    }
+}
+
+fn is_field(field: &str) -> bool {
+    matches!(
+        field,
+        "dir" | "exit" | "host" | "session" | "after" | "before" | "dur"
+    )
+}
+
+fn parse_filter(
+    field: &str,
+    value: &str,
+    context: &ScopeContext,
+    now: DateTime<Utc>,
+) -> Option<FieldFilter> {
+    if value.is_empty() {
+        return None;
+    }
+
+    match field {
+        "dir" => Some(FieldFilter::Directory(resolve_directory(value, &context.current_dir))),
+        "exit" => value.parse().ok().map(FieldFilter::ExitCode),
+        "host" => Some(FieldFilter::Host(value.to_string())),
+        "session" if value == "current" => Some(FieldFilter::Session(context.session_id.clone())),
+        "session" => Some(FieldFilter::Session(value.to_string())),
+        "after" => parse_time(value, now).map(FieldFilter::After),
+        "before" => parse_time(value, now).map(FieldFilter::Before),
+        "dur" => {
+            let (ordering, inclusive, rest) = parse_comparison(value);
+            parse_duration(rest)
+                .ok()
+                .map(|duration| FieldFilter::Duration(ordering, inclusive, duration))
+        }
+        _ => None,
+    }
+}
+
+fn resolve_directory(value: &str, current_dir: &str) -> String {
+    let expanded = match value.strip_prefix('~') {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => format!("{}{}", home.to_string_lossy(), rest),
+            None => value.to_string(),
+        },
+        None => value.to_string(),
+    };
+
+    let path = if expanded.starts_with('/') {
+        expanded
+    } else if expanded == "." {
+        current_dir.to_string()
+    } else {
+        format!("{}/{}", current_dir, expanded.trim_start_matches("./"))
+    };
+
+    path.trim_end_matches('/').to_string()
+}
+
+/// `2d` means two days ago; otherwise a date (local midnight) or RFC 3339 time.
+///
+/// AIDEV-NOTE: the TUI re-parses on every keystroke, so a duration reaching
+/// past the representable range must drop the filter, never panic
+fn parse_time(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(ago) = parse_duration(value) {
+        return now.checked_sub_signed(ago);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Split a leading `>`, `>=`, `<`, `<=` or `=`; no operator means "at least".
+fn parse_comparison(value: &str) -> (Ordering, bool, &str) {
+    if let Some(rest) = value.strip_prefix(">=") {
+        (Ordering::Greater, true, rest)
+    } else if let Some(rest) = value.strip_prefix("<=") {
+        (Ordering::Less, true, rest)
+    } else if let Some(rest) = value.strip_prefix('>') {
+        (Ordering::Greater, false, rest)
+    } else if let Some(rest) = value.strip_prefix('<') {
+        (Ordering::Less, false, rest)
+    } else if let Some(rest) = value.strip_prefix('=') {
+        (Ordering::Equal, true, rest)
+    } else {
+        (Ordering::Greater, true, value)
+    }
+}
+
+impl FieldFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        match self {
+            FieldFilter::Directory(dir) => is_within(&entry.directory, dir),
+            FieldFilter::ExitCode(code) => entry.exit_code == *code,
+            FieldFilter::Host(host) => entry.hostname.as_deref() == Some(host.as_str()),
+            FieldFilter::Session(session) => entry.session_id == *session,
+            FieldFilter::After(time) => entry.timestamp >= *time,
+            FieldFilter::Before(time) => entry.timestamp < *time,
+            FieldFilter::Duration(ordering, inclusive, limit) => match entry.duration() {
+                Some(duration) => {
+                    let actual = duration.cmp(limit);
+                    actual == *ordering || (*inclusive && actual == Ordering::Equal)
+                }
+                // Entries recorded without start/end have no duration to compare
+                None => false,
+            },
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::history::HistoryEntry;
+
+/// Relative importance of each ranking signal; all signals are in `0..=1`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RankingWeights {
+    /// How well the query text matched
+    #[serde(rename = "match")]
+    pub match_score: f64,
+    /// How often the command was run
+    pub frequency: f64,
+    /// How recently this entry ran, decaying with `half_life_hours`
+    pub recency: f64,
+    /// Fraction of runs of the command that exited 0
+    pub success: f64,
+    /// How close the entry's directory is to the current one
+    pub proximity: f64,
+    #[serde(rename = "half_life")]
+    pub half_life_hours: f64,
+}
+
+impl Default for RankingWeights {
+    fn default() -> Self {
+        Self {
+            match_score: 1.0,
+            frequency: 0.5,
+            recency: 0.8,
+            success: 0.3,
+            proximity: 0.4,
+            half_life_hours: 24.0 * 7.0,
+        }
+    }
+}
+
+impl RankingWeights {
+    /// Apply overrides like `match=1,recency=2,half-life=48` on top of these weights.
+    pub fn with_overrides(self, input: &str) -> Result<Self> {
+        let mut weights = self;
+
+        for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected name=value, got '{}'", part))?;
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid weight '{}' for {}", value, name))?;
+
+            match name.trim() {
+                "match" => weights.match_score = value,
+                "frequency" => weights.frequency = value,
+                "recency" => weights.recency = value,
+                "success" => weights.success = value,
+                "proximity" => weights.proximity = value,
+                "half-life" | "half_life" => weights.half_life_hours = value,
+                other => bail!(
+                    "unknown ranking weight '{}' (expected match, frequency, recency, success, proximity or half-life)",
+                    other
+                ),
+            }
+        }
+
+        Ok(weights)
+    }
+}
+
+#[derive(Default)]
+struct CommandStats {
+    runs: u64,
+    successes: u64,
+}
+
+/// Orders matched entries by a weighted blend of match quality and frecency.
+#[derive(Debug, Clone)]
+pub struct Ranker {
+    weights: RankingWeights,
+    current_dir: String,
+}
+
+impl Ranker {
+    pub fn new(weights: RankingWeights, current_dir: &str) -> Self {
+        Self {
+            weights,
+            current_dir: current_dir.to_string(),
+        }
+    }
+
+    /// Rank `matched` (entries paired with their raw match score), best first.
+    ///
+    /// Frequency and success rate are computed over `population`, the entries
+    /// the query was run against, so they reflect the active scope.
+    pub fn rank<'a>(
+        &self,
+        matched: Vec<(i64, &'a HistoryEntry)>,
+        population: &[&HistoryEntry],
+    ) -> Vec<&'a HistoryEntry> {
+        let now = Utc::now();
+
+        let mut stats: HashMap<&str, CommandStats> = HashMap::new();
+        for entry in population {
+            let command_stats = stats.entry(entry.command.as_str()).or_default();
+            command_stats.runs += entry.count as u64;
+            if entry.exit_code == 0 {
+                command_stats.successes += entry.count as u64;
+            }
+        }
+        let max_runs = stats.values().map(|s| s.runs).max().unwrap_or(1).max(1);
+
+        // AIDEV-NOTE: raw scores differ wildly between match modes (fuzzy
+        // scores vs. negative positions), so normalise them to 0..=1 per query
+        let min_score = matched.iter().map(|(score, _)| *score).min().unwrap_or(0);
+        let max_score = matched.iter().map(|(score, _)| *score).max().unwrap_or(0);
+        let score_range = (max_score - min_score).max(1) as f64;
+
+        let mut ranked: Vec<(f64, &HistoryEntry)> = matched
+            .into_iter()
+            .map(|(score, entry)| {
+                let command_stats = stats.get(entry.command.as_str());
+                let runs = command_stats.map_or(entry.count as u64, |s| s.runs);
+                let successes = command_stats.map_or(0, |s| s.successes);
+
+                let match_signal = if max_score == min_score {
+                    1.0
+                } else {
+                    (score - min_score) as f64 / score_range
+                };
+                let frequency = (1.0 + runs as f64).ln() / (1.0 + max_runs as f64).ln();
+                let success = successes as f64 / runs.max(1) as f64;
+
+                let total = self.weights.match_score * match_signal
+                    + self.weights.frequency * frequency
+                    + self.weights.recency * self.recency(entry.timestamp, now)
+                    + self.weights.success * success
+                    + self.weights.proximity * self.proximity(&entry.directory);
+                (total, entry)
+            })
+            .collect();
+
+        ranked.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| b.1.timestamp.cmp(&a.1.timestamp))
+        });
+
+        ranked.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    fn recency(&self, timestamp: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+        let age_hours = now.signed_duration_since(timestamp).num_seconds().max(0) as f64 / 3600.0;
+        if self.weights.half_life_hours <= 0.0 {
+            return 0.0;
+        }
+        (-std::f64::consts::LN_2 * age_hours / self.weights.half_life_hours).exp()
+    }
+
+    /// 1.0 in the current directory, falling off with every path component
+    /// separating the two directories.
+    fn proximity(&self, directory: &str) -> f64 {
+        let current: Vec<_> = Path::new(&self.current_dir).components().collect();
+        let other: Vec<_> = Path::new(directory).components().collect();
+
+        let common = current
+            .iter()
+            .zip(&other)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let distance = (current.len() - common) + (other.len() - common);
+
+        1.0 / (1.0 + distance as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn entry(command: &str, directory: &str, exit_code: i32, hours_ago: i64) -> HistoryEntry {
+        HistoryEntry::test(command, directory, exit_code, Utc::now() - Duration::hours(hours_ago))
+    }
+
+    /// Weights that rank by `signal` alone.
+    fn only(signal: &str) -> RankingWeights {
+        RankingWeights::default()
+            .with_overrides(&format!("match=0,frequency=0,recency=0,success=0,proximity=0,{}=1", signal))
+            .unwrap()
+    }
+
+    /// Commands of `entries` ranked with equal match scores, best first.
+    fn rank(weights: RankingWeights, entries: &[HistoryEntry]) -> Vec<&str> {
+        let population: Vec<_> = entries.iter().collect();
+        let matched = entries.iter().map(|entry| (0, entry)).collect();
+        Ranker::new(weights, "/src/app")
+            .rank(matched, &population)
+            .into_iter()
+            .map(|entry| entry.command.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn overrides_replace_named_weights() {
+        let weights = RankingWeights::default()
+            .with_overrides(" match = 2, half-life=48,,recency=0 ,")
+            .unwrap();
+        assert_eq!(weights.match_score, 2.0);
+        assert_eq!(weights.half_life_hours, 48.0);
+        assert_eq!(weights.recency, 0.0);
+        assert_eq!(weights.frequency, RankingWeights::default().frequency);
+
+        let weights = RankingWeights::default().with_overrides("half_life=12").unwrap();
+        assert_eq!(weights.half_life_hours, 12.0);
+        assert_eq!(RankingWeights::default().with_overrides("").unwrap(), RankingWeights::default());
+    }
+
+    #[test]
+    fn malformed_overrides_are_errors() {
+        for (input, message) in [
+            ("match", "expected name=value"),
+            ("match=", "invalid weight"),
+            ("match=high", "invalid weight 'high' for match"),
+            ("recency=1,speed=2", "unknown ranking weight 'speed'"),
+            ("=1", "unknown ranking weight ''"),
+        ] {
+            let error = RankingWeights::default().with_overrides(input).unwrap_err();
+            assert!(error.to_string().contains(message), "{}: {}", input, error);
+        }
+    }
+
+    #[test]
+    fn recency_halves_every_half_life() {
+        let ranker = Ranker::new(RankingWeights::default().with_overrides("half-life=10").unwrap(), "/");
+        let now = Utc::now();
+        assert!((ranker.recency(now, now) - 1.0).abs() < 1e-9);
+        assert!((ranker.recency(now - Duration::hours(10), now) - 0.5).abs() < 1e-9);
+        assert!((ranker.recency(now - Duration::hours(20), now) - 0.25).abs() < 1e-9);
+        // Clock skew doesn't push entries from the future above 1
+        assert!((ranker.recency(now + Duration::hours(1), now) - 1.0).abs() < 1e-9);
+
+        let off = Ranker::new(RankingWeights::default().with_overrides("half-life=0").unwrap(), "/");
+        assert_eq!(off.recency(now, now), 0.0);
+
+        let entries = [entry("old", "/", 0, 24 * 30), entry("new", "/", 0, 1), entry("week", "/", 0, 24 * 7)];
+        assert_eq!(rank(only("recency"), &entries), ["new", "week", "old"]);
+    }
+
+    #[test]
+    fn proximity_falls_off_with_distance() {
+        let ranker = Ranker::new(RankingWeights::default(), "/src/app");
+        assert_eq!(ranker.proximity("/src/app"), 1.0);
+        assert_eq!(ranker.proximity("/src/app/lib"), 0.5);
+        assert_eq!(ranker.proximity("/src/web"), 1.0 / 3.0);
+
+        let entries = [entry("far", "/etc", 0, 0), entry("here", "/src/app", 0, 0), entry("near", "/src", 0, 0)];
+        assert_eq!(rank(only("proximity"), &entries), ["here", "near", "far"]);
+    }
+
+    #[test]
+    fn success_and_frequency_are_per_command() {
+        let entries = [
+            entry("flaky", "/", 1, 3),
+            entry("flaky", "/", 0, 2),
+            entry("solid", "/", 0, 1),
+            entry("broken", "/", 2, 0),
+        ];
+        // Equal totals fall back to the newest entry first
+        assert_eq!(rank(only("success"), &entries), ["solid", "flaky", "flaky", "broken"]);
+        assert_eq!(rank(only("frequency"), &entries), ["flaky", "flaky", "broken", "solid"]);
+    }
+
+    #[test]
+    fn match_scores_are_normalised_per_query() {
+        let entries = [entry("weak", "/", 0, 0), entry("strong", "/", 0, 5)];
+        let population: Vec<_> = entries.iter().collect();
+        let matched = vec![(-40, &entries[0]), (250, &entries[1])];
+
+        let ranked = Ranker::new(RankingWeights::default(), "/src/app").rank(matched, &population);
+        assert_eq!(ranked[0].command, "strong");
+    }
+}
@@ -1,19 +1,34 @@
-use anyhow::Result;
-use chrono::Utc;
-use console::style;
+use anyhow::{anyhow, bail, Result};
+use chrono::{Local, Utc};
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyModifiers},
+    event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{self, disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, size},
 };
-use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use clap::ValueEnum;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{ControlFlow, Range};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 use std::io::{self, Write, stdout};
 
-use crate::history::{HistoryEntry, SearchScope};
+use crate::duration::format_duration;
+use crate::export::{self, ExportFormat};
+use crate::history::{HistoryEntry, ScopeContext, SearchScope};
+use crate::keymap::{Action, Keymap};
+use crate::line_editor::LineEditor;
+use crate::matcher::{MatchMode, Pattern};
+use crate::query::Query;
+use crate::ranking::Ranker;
+use crate::storage::{EntryFilter, Storage};
+use crate::theme::Styles;
 
 // AIDEV-NOTE: format timestamp as human-readable relative time
-fn format_relative_time(timestamp: &chrono::DateTime<chrono::Utc>) -> String {
+pub fn format_relative_time(timestamp: &chrono::DateTime<chrono::Utc>) -> String {
     let now = Utc::now();
     let duration = now.signed_duration_since(*timestamp);
     
@@ -37,179 +52,841 @@ fn format_relative_time(timestamp: &chrono::DateTime<chrono::Utc>) -> String {
 
 pub struct FuzzySearcher {
     matcher: SkimMatcherV2,
+    mode: MatchMode,
+    ranker: Ranker,
+    dedup: bool,
 }
 
 impl FuzzySearcher {
-    pub fn new() -> Self {
+    pub fn new(mode: MatchMode, ranker: Ranker, dedup: bool) -> Self {
         Self {
             matcher: SkimMatcherV2::default(),
+            mode,
+            ranker,
+            dedup,
         }
     }
 
-    pub fn search(&self, query: &str, entries: &[HistoryEntry], max_results: usize) -> Vec<HistoryEntry> {
-        let mut scored_entries: Vec<(i64, &HistoryEntry)> = entries
+    pub fn mode(&self) -> MatchMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: MatchMode) {
+        self.mode = mode;
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.dedup
+    }
+
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
+    /// Character positions in `command` matched by `pattern`, for highlighting.
+    pub fn match_indices(&self, pattern: &Pattern, command: &str) -> Vec<usize> {
+        pattern.indices(&self.matcher, command)
+    }
+
+    /// Apply the query's field filters, then match its free text using the
+    /// current mode.
+    ///
+    /// Matches are returned best first, ranked by match quality and frecency.
+    /// `entries` are expected oldest first; a query with no free text returns
+    /// the most recent matches, newest first.
+    ///
+    /// With dedup on, each command appears once, see [`dedup_commands`].
+    pub fn search(&self, query: &Query, entries: &[HistoryEntry], max_results: usize) -> Vec<HistoryEntry> {
+        let candidates: Vec<&HistoryEntry> = entries
             .iter()
-            .filter_map(|entry| {
-                self.matcher
-                    .fuzzy_match(&entry.command, query)
+            .filter(|entry| query.matches(entry))
+            .collect();
+
+        if query.text.is_empty() {
+            let recent = candidates.iter().rev().copied();
+            if self.dedup {
+                let mut unique = dedup_commands(recent);
+                unique.truncate(max_results);
+                return unique;
+            }
+            return recent.take(max_results).cloned().collect();
+        }
+
+        let pattern = Pattern::new(self.mode, &query.text);
+        let scored_entries: Vec<(i64, &HistoryEntry)> = candidates
+            .iter()
+            .filter_map(|&entry| {
+                pattern
+                    .score(&self.matcher, &entry.command)
                     .map(|score| (score, entry))
             })
             .collect();
 
-        // AIDEV-NOTE: sort by score descending, then by timestamp for ties
-        scored_entries.sort_by(|a, b| {
-            b.0.cmp(&a.0).then_with(|| b.1.timestamp.cmp(&a.1.timestamp))
-        });
+        let ranked = self.ranker.rank(scored_entries, &candidates);
+        // AIDEV-NOTE: collapse before truncating, otherwise one popular command
+        // could fill every slot
+        if self.dedup {
+            let mut unique = dedup_commands(ranked.into_iter());
+            unique.truncate(max_results);
+            unique
+        } else {
+            ranked.into_iter().take(max_results).cloned().collect()
+        }
+    }
 
-        scored_entries
-            .into_iter()
-            .take(max_results)
-            .map(|(_, entry)| entry.clone())
+    /// Every entry matching the query's field filters and free text, in the
+    /// order given and without ranking, dedup or a limit.
+    pub fn filter(&self, query: &Query, entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+        let pattern = Pattern::new(self.mode, &query.text);
+        entries
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .filter(|entry| query.text.is_empty() || pattern.score(&self.matcher, &entry.command).is_some())
+            .cloned()
             .collect()
     }
 }
 
-pub struct InteractiveSearcher {
-    all_entries: Vec<HistoryEntry>,
+/// Collapse identical commands into one entry each, in order of each
+/// command's first appearance.
+///
+/// The entry kept is the command's newest occurrence (so it shows the last
+/// time it ran and its last exit status) with `count` covering every run.
+pub fn dedup_commands<'a>(entries: impl Iterator<Item = &'a HistoryEntry>) -> Vec<HistoryEntry> {
+    let mut order: Vec<&str> = Vec::new();
+    let mut groups: HashMap<&str, (&HistoryEntry, u32)> = HashMap::new();
+
+    for entry in entries {
+        match groups.get_mut(entry.command.as_str()) {
+            Some((newest, runs)) => {
+                if entry.timestamp > newest.timestamp {
+                    *newest = entry;
+                }
+                *runs += entry.count;
+            }
+            None => {
+                order.push(&entry.command);
+                groups.insert(&entry.command, (entry, entry.count));
+            }
+        }
+    }
+
+    order
+        .into_iter()
+        .map(|command| {
+            let (newest, runs) = groups[command];
+            HistoryEntry {
+                count: runs,
+                ..newest.clone()
+            }
+        })
+        .collect()
+}
+
+/// Columns `text` occupies in the terminal.
+fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// The graphemes of `text` that fit in `max_width` columns, each with the
+/// range of chars it covers, and whether the rest had to be cut off. When it
+/// is, one column is left free for an ellipsis.
+///
+/// AIDEV-NOTE: tabs, newlines and other control characters are shown as a
+/// single space so multi-line commands stay on one row.
+fn fit_graphemes(text: &str, max_width: usize) -> (Vec<(Range<usize>, &str)>, bool) {
+    let mut graphemes = Vec::new();
+    let mut char_index = 0;
+    for grapheme in text.graphemes(true) {
+        let shown = if grapheme.chars().any(char::is_control) { " " } else { grapheme };
+        let chars = grapheme.chars().count();
+        graphemes.push((char_index..char_index + chars, shown));
+        char_index += chars;
+    }
+
+    let total: usize = graphemes.iter().map(|(_, g)| display_width(g)).sum();
+    if total <= max_width {
+        return (graphemes, false);
+    }
+
+    let budget = max_width.saturating_sub(1);
+    let mut used = 0;
+    let fitting = graphemes
+        .iter()
+        .take_while(|(_, g)| {
+            used += display_width(g);
+            used <= budget
+        })
+        .count();
+    graphemes.truncate(fitting);
+    (graphemes, true)
+}
+
+/// Render the command column: a leading space, then the command cut to
+/// `max_width` columns with an ellipsis, with the `matched` character
+/// positions highlighted.
+fn styled_command(
+    command: &str,
+    matched: &[usize],
+    max_width: usize,
+    selected: bool,
+    styles: &Styles,
+) -> String {
+    let (graphemes, truncated) = fit_graphemes(command, max_width);
+    let style_for = |is_match: bool| match (selected, is_match) {
+        (true, true) => &styles.selected_match,
+        (true, false) => &styles.selected_text,
+        (false, true) => &styles.matched,
+        (false, false) => &styles.text,
+    };
+
+    let mut rendered = if selected {
+        styles.selection.apply_to(" ").to_string()
+    } else {
+        " ".to_string()
+    };
+    let mut visible_chars = 0;
+    for (chars, grapheme) in graphemes {
+        visible_chars = chars.end;
+        let is_match = chars.clone().any(|i| matched.binary_search(&i).is_ok());
+        rendered.push_str(&style_for(is_match).apply_to(grapheme).to_string());
+    }
+    if truncated && max_width > 0 {
+        // The match continues past the cut-off
+        let hidden_match = matched.last().is_some_and(|&last| last >= visible_chars);
+        rendered.push_str(&style_for(hidden_match).apply_to('…').to_string());
+    }
+    rendered
+}
+
+/// Wrap `text` into lines of at most `width` columns, keeping its own line
+/// breaks. Tabs and other control characters become spaces.
+fn wrap_to_width(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+
+    for source_line in text.split('\n') {
+        let mut line = String::new();
+        let mut line_width = 0;
+        for grapheme in source_line.trim_end_matches('\r').graphemes(true) {
+            let shown = if grapheme.chars().any(char::is_control) { " " } else { grapheme };
+            let grapheme_width = display_width(shown);
+            if line_width + grapheme_width > width && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+            line.push_str(shown);
+            line_width += grapheme_width;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Cut `text` to at most `max_width` columns, ending in `…` if shortened.
+fn truncate_to_width(text: &str, max_width: usize) -> String {
+    let (graphemes, truncated) = fit_graphemes(text, max_width);
+    let mut result: String = graphemes.into_iter().map(|(_, g)| g).collect();
+    if truncated && max_width > 0 {
+        result.push('…');
+    }
+    result
+}
+
+/// What the shell should do with the chosen command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptAction {
+    /// Run it immediately
+    Execute,
+    /// Put it on the command line for editing
+    Edit,
+}
+
+impl AcceptAction {
+    pub fn label(self) -> &'static str {
+        match self {
+            AcceptAction::Execute => "execute",
+            AcceptAction::Edit => "edit",
+        }
+    }
+}
+
+/// The command picked in the interactive search and how it was accepted.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub command: String,
+    pub action: AcceptAction,
+}
+
+impl Selection {
+    /// What `--output-file` holds for the shell widgets: the action on the
+    /// first line and the command, which may span several, after it.
+    ///
+    /// AIDEV-NOTE: rehash.bash and rehash.zsh split this at the first
+    /// newline; keep them in step with any change here
+    pub fn to_output_file(&self) -> String {
+        format!("{}\n{}", self.action.label(), self.command)
+    }
+}
+
+/// Where the details of the selected entry are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewLayout {
+    Hidden,
+    /// Below the list, above the prompt
+    Bottom,
+    /// Beside the list
+    Right,
+}
+
+impl PreviewLayout {
+    pub fn next(self) -> Self {
+        match self {
+            PreviewLayout::Hidden => PreviewLayout::Bottom,
+            PreviewLayout::Bottom => PreviewLayout::Right,
+            PreviewLayout::Right => PreviewLayout::Hidden,
+        }
+    }
+}
+
+/// How much of the terminal the picker takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Height {
+    /// The whole terminal, on the alternate screen
+    Full,
+    /// A fixed number of lines below the cursor
+    Lines(u16),
+    /// A percentage of the terminal's lines below the cursor
+    Percent(u16),
+}
+
+impl Height {
+    /// Used by `--inline` when no `--height` is given.
+    pub const INLINE: Height = Height::Percent(40);
+
+    /// Parse `full`, `20` (lines) or `40%`.
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if input == "full" {
+            return Ok(Height::Full);
+        }
+        let (digits, percent) = match input.strip_suffix('%') {
+            Some(digits) => (digits, true),
+            None => (input, false),
+        };
+        let value: u16 = digits
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid height '{}' (expected lines like 20 or a percentage like 40%)", input))?;
+        match (percent, value) {
+            (_, 0) => bail!("height must be greater than zero"),
+            (true, 100..) => Ok(Height::Full),
+            (true, value) => Ok(Height::Percent(value)),
+            (false, value) => Ok(Height::Lines(value)),
+        }
+    }
+
+    /// Rows to use on a terminal `rows` tall, leaving the line the picker
+    /// was started from visible when inline.
+    fn rows(self, rows: u16) -> u16 {
+        // Header, at least one entry and the prompt
+        const MIN_ROWS: u16 = 3;
+        let wanted = match self {
+            Height::Full => return rows,
+            Height::Lines(lines) => lines,
+            Height::Percent(percent) => (rows as u32 * percent as u32 / 100) as u16,
+        };
+        wanted.max(MIN_ROWS).min(rows.saturating_sub(1))
+    }
+}
+
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Height::Full => write!(f, "full"),
+            Height::Lines(lines) => write!(f, "{}", lines),
+            Height::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl Serialize for Height {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Height::Lines(lines) => serializer.serialize_u16(*lines),
+            other => serializer.serialize_str(&other.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Height {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        // `height = 20` as well as `height = "20"`, `"40%"` or `"full"`
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Lines(u16),
+            Text(String),
+        }
+
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Lines(lines) => lines.to_string(),
+            Raw::Text(text) => text,
+        };
+        Height::parse(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Presentation settings for the interactive search.
+#[derive(Debug, Clone)]
+pub struct PickerOptions {
+    pub join: JoinStyle,
+    pub height: Height,
+    /// Preview pane layout at startup (F6 cycles it)
+    pub preview: PreviewLayout,
+    /// Most results listed for a non-empty query
+    pub limit: usize,
+    /// Fraction of the list kept between the selection and the edge when scrolling
+    pub scroll_threshold: f64,
+    pub styles: Styles,
+    pub keymap: Keymap,
+}
+
+impl Default for PickerOptions {
+    fn default() -> Self {
+        Self {
+            join: JoinStyle::Newline,
+            height: Height::Full,
+            preview: PreviewLayout::Hidden,
+            limit: 50,
+            scroll_threshold: 0.35,
+            styles: Styles::default(),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+/// Screen areas for one frame.
+struct Layout {
+    list_rows: usize,
+    list_width: usize,
+    preview: Option<Pane>,
+}
+
+/// Smallest list left beside or above a preview pane
+const MIN_LIST_ROWS: usize = 4;
+const MIN_LIST_WIDTH: usize = 40;
+/// Smallest preview pane worth drawing, separator included
+const MIN_PREVIEW_ROWS: usize = 4;
+const MIN_PREVIEW_WIDTH: usize = 20;
+
+/// A preview pane, including the one-cell separator on its top or left edge.
+struct Pane {
+    x: u16,
+    y: u16,
+    width: usize,
+    height: usize,
+}
+
+/// A deletion waiting for confirmation on the prompt line.
+struct PendingDelete {
+    /// The marked entries, or just the selected one
+    entries: Vec<HistoryEntry>,
+    /// Whether deleting just `entries` is offered, rather than only every run
+    single: bool,
+    /// Runs of their commands in the primary store, which is all that can be deleted
+    runs: u32,
+}
+
+/// An export waiting on the prompt line for the name of its file.
+struct PendingExport {
+    /// The marked entries, or just the selected one
+    entries: Vec<HistoryEntry>,
+    path: LineEditor,
+}
+
+/// How several marked commands are combined into one result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinStyle {
+    /// One command per line, like a script
+    Newline,
+    /// `first && second`, stopping at the first failure
+    And,
+}
+
+impl JoinStyle {
+    fn separator(self) -> &'static str {
+        match self {
+            JoinStyle::Newline => "\n",
+            JoinStyle::And => " && ",
+        }
+    }
+}
+
+pub struct InteractiveSearcher<'a> {
+    storage: &'a Storage,
+    /// Entries in the current scope, oldest first
+    scope_entries: Vec<HistoryEntry>,
     filtered_entries: Vec<HistoryEntry>,
-    query: String,
+    query: LineEditor,
     selected_index: usize,
     scroll_offset: usize,
     searcher: FuzzySearcher,
     current_scope: SearchScope,
-    current_dir: String,
-    session_id: String,
+    context: ScopeContext,
+    /// Command whose individual runs are listed instead of the search results
+    expanded: Option<String>,
+    /// Free text of the current query, compiled for highlighting matches
+    highlight: Option<Pattern>,
+    preview: PreviewLayout,
+    pending_delete: Option<PendingDelete>,
+    pending_export: Option<PendingExport>,
+    /// Shown on the prompt line until the next key press
+    message: Option<String>,
+    /// Entries marked for a batch action, in the order they were marked
+    marked: Vec<HistoryEntry>,
+    options: PickerOptions,
+    /// In vi normal mode keys act on the list instead of typing
+    normal_mode: bool,
+    /// Terminal row of the picker's first line; 0 unless inline
+    top: u16,
+    /// Where the cursor was before an inline picker was drawn below it
+    origin: (u16, u16),
 }
 
-impl InteractiveSearcher {
-    pub fn new(
-        all_entries: Vec<HistoryEntry>, 
-        initial_scope: SearchScope, 
-        current_dir: &str, 
-        session_id: &str
-    ) -> Self {
-        Self::new_with_prefix(all_entries, initial_scope, current_dir, session_id, None)
-    }
-
+impl<'a> InteractiveSearcher<'a> {
     pub fn new_with_prefix(
-        all_entries: Vec<HistoryEntry>, 
+        storage: &'a Storage,
         initial_scope: SearchScope, 
-        current_dir: &str, 
-        session_id: &str,
+        searcher: FuzzySearcher,
+        context: ScopeContext,
         prefix: Option<String>
-    ) -> Self {
+    ) -> Result<Self> {
+        // AIDEV-NOTE: the store applies the scope, so SQLite can use its
+        // indexes instead of every entry being loaded and filtered here
+        let scope_entries = storage.get_entries(&context.filter(initial_scope))?;
         let mut searcher = Self {
-            all_entries,
+            storage,
+            scope_entries,
             filtered_entries: Vec::new(),
-            query: prefix.unwrap_or_default(),
+            query: LineEditor::new(prefix.unwrap_or_default()),
             selected_index: 0,
             scroll_offset: 0,
-            searcher: FuzzySearcher::new(),
+            searcher,
             current_scope: initial_scope,
-            current_dir: current_dir.to_string(),
-            session_id: session_id.to_string(),
+            context,
+            expanded: None,
+            highlight: None,
+            preview: PreviewLayout::Hidden,
+            pending_delete: None,
+            pending_export: None,
+            message: None,
+            marked: Vec::new(),
+            options: PickerOptions::default(),
+            normal_mode: false,
+            top: 0,
+            origin: (0, 0),
         };
         
         searcher.update_filter();
-        searcher
+        Ok(searcher)
     }
 
-    pub fn run(mut self) -> Result<Option<String>> {
+    pub fn set_options(&mut self, options: PickerOptions) {
+        self.preview = options.preview;
+        self.options = options;
+        self.update_filter();
+    }
+
+    pub fn run(mut self) -> Result<Option<Selection>> {
         enable_raw_mode()?;
-        execute!(io::stdout(), EnterAlternateScreen)?;
+        if self.options.height == Height::Full {
+            execute!(io::stdout(), EnterAlternateScreen, EnableBracketedPaste)?;
+        } else {
+            self.reserve_inline_rows()?;
+            execute!(io::stdout(), EnableBracketedPaste)?;
+        }
 
         let result = self.main_loop();
 
         disable_raw_mode()?;
-        execute!(io::stdout(), LeaveAlternateScreen)?;
+        if self.options.height == Height::Full {
+            execute!(io::stdout(), DisableBracketedPaste, LeaveAlternateScreen)?;
+        } else {
+            // Wipe the picker and put the cursor back where it started
+            execute!(
+                io::stdout(),
+                DisableBracketedPaste,
+                cursor::MoveTo(0, self.top),
+                terminal::Clear(terminal::ClearType::FromCursorDown),
+                cursor::MoveTo(self.origin.0, self.origin.1),
+            )?;
+        }
 
         result
     }
 
-    fn main_loop(&mut self) -> Result<Option<String>> {
+    /// Make room for an inline picker below the cursor, scrolling the
+    /// terminal up if the cursor is too close to the bottom.
+    fn reserve_inline_rows(&mut self) -> Result<()> {
+        let (_, rows) = size()?;
+        let height = self.options.height.rows(rows);
+        let (column, _) = cursor::position()?;
+
+        // AIDEV-NOTE: printing newlines rather than computing the scroll
+        // ourselves keeps the scrolled-off lines in the terminal's scrollback
+        let mut stdout = stdout();
+        write!(stdout, "{}", "\n".repeat(height as usize))?;
+        stdout.flush()?;
+        let (_, bottom) = cursor::position()?;
+
+        self.top = (bottom + 1).saturating_sub(height);
+        self.origin = (column, self.top.saturating_sub(1));
+        Ok(())
+    }
+
+    /// Size of the area the picker draws in.
+    fn viewport(&self) -> (u16, u16) {
+        let (cols, rows) = size().unwrap_or((80, 24));
+        (cols, self.options.height.rows(rows))
+    }
+
+    /// Move to `(column, row)` within the picker.
+    fn move_to(&self, column: u16, row: u16) -> cursor::MoveTo {
+        cursor::MoveTo(column, self.top + row)
+    }
+
+    fn main_loop(&mut self) -> Result<Option<Selection>> {
         loop {
             self.render()?;
 
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        return Ok(None);
-                    }
-                    KeyCode::Esc => {
-                        return Ok(None);
-                    }
-                    KeyCode::Enter => {
-                        if let Some(entry) = self.filtered_entries.get(self.selected_index) {
-                            return Ok(Some(entry.command.clone()));
-                        }
-                        return Ok(None);
-                    }
-                    KeyCode::Up => {
-                        if self.selected_index > 0 {
-                            self.selected_index -= 1;
-                            self.update_scroll();
+            let event = event::read()?;
+            if let Event::Paste(text) = &event {
+                if let Some(pending) = &mut self.pending_export {
+                    pending.path.insert_str(text);
+                    continue;
+                }
+                self.query.insert_str(text);
+                self.update_filter();
+                self.update_scroll();
+                continue;
+            }
+
+            if let Event::Key(key) = event {
+                self.message = None;
+                if let Some(pending) = self.pending_delete.take() {
+                    self.confirm_delete(pending, key.code)?;
+                    continue;
+                }
+                if let Some(pending) = self.pending_export.take() {
+                    self.edit_export(pending, key);
+                    continue;
+                }
+
+                match self.options.keymap.action(self.normal_mode, key) {
+                    Some(action) => {
+                        if let ControlFlow::Break(selection) = self.perform(action)? {
+                            return Ok(selection);
                         }
                     }
-                    KeyCode::Down => {
-                        if self.selected_index < self.filtered_entries.len().saturating_sub(1) {
-                            self.selected_index += 1;
-                            self.update_scroll();
+                    // Unbound Ctrl/Alt chords shouldn't type their letter
+                    None if !self.normal_mode => {
+                        if let KeyCode::Char(c) = key.code {
+                            if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+                                self.query.insert_char(c);
+                                self.update_filter();
+                                self.update_scroll();
+                            }
                         }
                     }
-                    // AIDEV-NOTE: hotkeys for scope switching
-                    KeyCode::F(1) => {
-                        self.current_scope = SearchScope::Global;
-                        self.update_filter();
-                        self.update_scroll();
-                    }
-                    KeyCode::F(2) => {
-                        self.current_scope = SearchScope::Session;
-                        self.update_filter();
-                        self.update_scroll();
-                    }
-                    KeyCode::F(3) => {
-                        self.current_scope = SearchScope::Local;
-                        self.update_filter();
-                        self.update_scroll();
-                    }
-                    KeyCode::Tab => {
-                        // AIDEV-NOTE: cycle through scopes with Tab
-                        self.current_scope = match self.current_scope {
-                            SearchScope::Global => SearchScope::Session,
-                            SearchScope::Session => SearchScope::Local,
-                            SearchScope::Local => SearchScope::Global,
-                        };
-                        self.update_filter();
-                        self.update_scroll();
-                    }
-                    KeyCode::Char(c) => {
-                        self.query.push(c);
-                        self.update_filter();
-                        self.update_scroll();
-                    }
-                    KeyCode::Backspace => {
-                        self.query.pop();
-                        self.update_filter();
-                        self.update_scroll();
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Carry out `action`; `Break` ends the search with its result.
+    fn perform(&mut self, action: Action) -> Result<ControlFlow<Option<Selection>>> {
+        match action {
+            // AIDEV-NOTE: Enter runs the command straight away; Tab, or
+            // Right at the end of the query, puts it on the line to edit
+            Action::Accept => return Ok(ControlFlow::Break(self.selection(AcceptAction::Execute))),
+            Action::Edit => return Ok(ControlFlow::Break(self.selection(AcceptAction::Edit))),
+            Action::ForwardCharOrEdit if self.query.at_end() => {
+                return Ok(ControlFlow::Break(self.selection(AcceptAction::Edit)));
+            }
+            Action::ForwardCharOrEdit | Action::ForwardChar => self.query.move_right(),
+            Action::Abort => return Ok(ControlFlow::Break(None)),
+            Action::Cancel if self.expanded.is_some() => self.collapse(),
+            Action::Cancel => return Ok(ControlFlow::Break(None)),
+            Action::Up => self.move_selection(-1),
+            Action::Down => self.move_selection(1),
+            Action::PageUp => self.move_selection(-(self.page_rows() as isize)),
+            Action::PageDown => self.move_selection(self.page_rows() as isize),
+            Action::Top => self.move_selection(isize::MIN),
+            Action::Bottom => self.move_selection(isize::MAX),
+            Action::ScopeGlobal => self.set_scope(SearchScope::Global)?,
+            Action::ScopeSession => self.set_scope(SearchScope::Session)?,
+            Action::ScopeLocal => self.set_scope(SearchScope::Local)?,
+            Action::ScopeHost => self.set_scope(SearchScope::Host)?,
+            Action::ScopeProject => self.set_scope(SearchScope::Project)?,
+            Action::NextScope => {
+                let next = match self.current_scope {
+                    SearchScope::Global => SearchScope::Session,
+                    SearchScope::Session => SearchScope::Local,
+                    SearchScope::Local => SearchScope::Host,
+                    SearchScope::Host => SearchScope::Project,
+                    SearchScope::Project => SearchScope::Global,
+                };
+                self.set_scope(next)?;
+            }
+            // AIDEV-NOTE: cycling match modes by default sits on Ctrl+R, like
+            // repeated Ctrl+R in readline
+            Action::CycleMode => {
+                self.searcher.set_mode(self.searcher.mode().next());
+                self.update_filter();
+            }
+            Action::ToggleDedup => {
+                self.searcher.set_dedup(!self.searcher.dedup());
+                self.update_filter();
+            }
+            Action::ToggleRuns => {
+                if self.expanded.is_some() {
+                    self.collapse();
+                } else if let Some(entry) = self.filtered_entries.get(self.selected_index) {
+                    self.expanded = Some(entry.command.clone());
+                    self.update_filter();
+                }
+            }
+            Action::TogglePreview => {
+                self.preview = self.preview.next();
+                self.update_scroll();
+            }
+            Action::ToggleMark => self.toggle_mark(),
+            // AIDEV-NOTE: a leading space means nothing in a query
+            Action::MarkOrSpace if self.query.is_empty() => self.toggle_mark(),
+            Action::MarkOrSpace => {
+                self.query.insert_char(' ');
+                self.update_filter();
+            }
+            Action::ToggleMarkAll => self.toggle_mark_all(),
+            // AIDEV-NOTE: deletion asks y/a/n on the prompt line first
+            Action::DeleteEntry => {
+                let entries = self.action_targets();
+                if !entries.is_empty() {
+                    // AIDEV-NOTE: only the primary store is written to, so runs
+                    // from read sources don't count towards what gets deleted
+                    let mut commands: Vec<&str> = entries.iter().map(|entry| entry.command.as_str()).collect();
+                    commands.sort_unstable();
+                    commands.dedup();
+                    let mut runs = 0;
+                    for command in commands {
+                        runs += self
+                            .storage
+                            .get_deletable_entries(&EntryFilter::command(command))?
+                            .iter()
+                            .map(|entry| entry.count)
+                            .sum::<u32>();
                     }
-                    _ => {}
+                    self.pending_delete = Some(PendingDelete {
+                        entries,
+                        // A collapsed row stands for every run
+                        single: self.expanded.is_some() || !self.searcher.dedup(),
+                        runs,
+                    });
                 }
             }
+            // AIDEV-NOTE: the file name is typed on the prompt line, and its
+            // extension picks the format
+            Action::Export => {
+                let entries = self.action_targets();
+                if !entries.is_empty() {
+                    self.pending_export = Some(PendingExport {
+                        entries,
+                        path: LineEditor::default(),
+                    });
+                }
+            }
+            Action::BackwardChar => self.query.move_left(),
+            Action::BeginningOfLine => self.query.move_home(),
+            Action::EndOfLine => self.query.move_end(),
+            Action::BackwardWord => self.query.move_word_left(),
+            Action::ForwardWord => self.query.move_word_right(),
+            Action::BackwardDeleteChar => {
+                self.query.delete_back();
+                self.update_filter();
+            }
+            Action::DeleteChar => {
+                self.query.delete_forward();
+                self.update_filter();
+            }
+            Action::UnixWordRubout => {
+                self.query.delete_word_back();
+                self.update_filter();
+            }
+            Action::UnixLineDiscard => {
+                self.query.kill_to_start();
+                self.update_filter();
+            }
+            Action::ViNormal => self.normal_mode = self.options.keymap.is_modal(),
+            Action::ViInsert => self.normal_mode = false,
+            Action::ViAppend => {
+                self.query.move_right();
+                self.normal_mode = false;
+            }
+            Action::ViInsertBol => {
+                self.query.move_home();
+                self.normal_mode = false;
+            }
+            Action::ViAppendEol => {
+                self.query.move_end();
+                self.normal_mode = false;
+            }
         }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn set_scope(&mut self, scope: SearchScope) -> Result<()> {
+        self.scope_entries = self.storage.get_entries(&self.context.filter(scope))?;
+        self.current_scope = scope;
+        self.update_filter();
+        Ok(())
+    }
+
+    /// Move the selection by `delta` rows, stopping at either end.
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.filtered_entries.len().saturating_sub(1);
+        self.selected_index = self.selected_index.saturating_add_signed(delta).min(last);
+        self.update_scroll();
+    }
+
+    fn page_rows(&self) -> usize {
+        let (cols, rows) = self.viewport();
+        self.layout(cols, rows).list_rows.max(1)
     }
 
     fn update_filter(&mut self) {
-        // AIDEV-NOTE: first filter by scope, then by query
-        let mut scope_filtered = self.filter_by_scope();
+        let query = Query::parse(self.query.text(), &self.context);
+        self.highlight = (!query.text.is_empty()).then(|| Pattern::new(self.searcher.mode(), &query.text));
         
-        if self.query.is_empty() {
-            // AIDEV-NOTE: sort by timestamp when no search query (oldest first)
-            scope_filtered.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-            self.filtered_entries = scope_filtered;
+        if let Some(command) = &self.expanded {
+            // Every run of the expanded command, oldest first
+            self.filtered_entries = self
+                .scope_entries
+                .iter()
+                .filter(|entry| entry.command == *command)
+                .cloned()
+                .collect();
         } else {
-            self.filtered_entries = self.searcher.search(&self.query, &scope_filtered, 50);
-            // AIDEV-NOTE: maintain timestamp order for search results too
-            self.filtered_entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+            // An empty prompt lists the whole scope; only queries are limited
+            let limit = if self.query.text().trim().is_empty() { usize::MAX } else { self.options.limit };
+            self.filtered_entries = self.searcher.search(&query, &self.scope_entries, limit);
+            // AIDEV-NOTE: the best match, or the newest entry for a query of
+            // filters alone, goes last, nearest the prompt where the selection
+            // starts
+            self.filtered_entries.reverse();
         }
         
         // AIDEV-NOTE: reset selection to most recent (last item) when filter changes
@@ -218,36 +895,243 @@ impl InteractiveSearcher {
         self.update_scroll();
     }
 
-    fn filter_by_scope(&self) -> Vec<HistoryEntry> {
-        match self.current_scope {
-            SearchScope::Global => self.all_entries.clone(),
-            SearchScope::Session => self.all_entries
+    fn selection(&self, action: AcceptAction) -> Option<Selection> {
+        let targets = self.action_targets();
+        let commands: Vec<&str> = targets
+            .iter()
+            .map(|entry| entry.command.as_str())
+            .collect();
+        if commands.is_empty() {
+            return None;
+        }
+        Some(Selection {
+            command: commands.join(self.options.join.separator()),
+            action,
+        })
+    }
+
+    /// What accept and delete act on: the marked entries, or else the selected one.
+    fn action_targets(&self) -> Vec<HistoryEntry> {
+        if !self.marked.is_empty() {
+            return self.marked.clone();
+        }
+        self.filtered_entries
+            .get(self.selected_index)
+            .cloned()
+            .into_iter()
+            .collect()
+    }
+
+    fn is_marked(&self, entry: &HistoryEntry) -> bool {
+        let filter = EntryFilter::entry(entry);
+        self.marked.iter().any(|marked| filter.matches(marked))
+    }
+
+    /// Mark or unmark the selected row and move on to the next one up.
+    fn toggle_mark(&mut self) {
+        let Some(entry) = self.filtered_entries.get(self.selected_index) else {
+            return;
+        };
+        if self.is_marked(entry) {
+            let filter = EntryFilter::entry(entry);
+            self.marked.retain(|marked| !filter.matches(marked));
+        } else {
+            self.marked.push(entry.clone());
+        }
+        // The list grows upwards from the prompt
+        if self.selected_index > 0 {
+            self.selected_index -= 1;
+            self.update_scroll();
+        }
+    }
+
+    /// Mark every visible row, or unmark them all if they already are.
+    fn toggle_mark_all(&mut self) {
+        let all_marked = self.filtered_entries.iter().all(|entry| self.is_marked(entry));
+        if all_marked {
+            let visible: Vec<EntryFilter> = self.filtered_entries.iter().map(EntryFilter::entry).collect();
+            self.marked.retain(|marked| !visible.iter().any(|filter| filter.matches(marked)));
+        } else {
+            let unmarked: Vec<HistoryEntry> = self
+                .filtered_entries
                 .iter()
-                .filter(|entry| entry.session_id == self.session_id)
+                .filter(|entry| !self.is_marked(entry))
                 .cloned()
-                .collect(),
-            SearchScope::Local => self.all_entries
+                .collect();
+            self.marked.extend(unmarked);
+        }
+    }
+
+    /// Act on the answer to a delete confirmation.
+    fn confirm_delete(&mut self, pending: PendingDelete, answer: KeyCode) -> Result<()> {
+        let filters: Vec<EntryFilter> = match answer {
+            KeyCode::Char('y') if pending.single => pending.entries.iter().map(EntryFilter::entry).collect(),
+            KeyCode::Char('y') | KeyCode::Char('a') => pending
+                .entries
                 .iter()
-                .filter(|entry| {
-                    entry.directory == self.current_dir || 
-                    entry.directory.starts_with(&format!("{}/", self.current_dir))
-                })
-                .cloned()
+                .map(|entry| EntryFilter::command(&entry.command))
                 .collect(),
+            _ => return Ok(()),
+        };
+
+        let mut removed = 0;
+        for filter in &filters {
+            removed += self.storage.delete_entries(filter)?;
+        }
+        // AIDEV-NOTE: read sources are never written to; reload rather than
+        // dropping their copies from the list only to see them return next time
+        self.scope_entries = self.storage.get_entries(&self.context.filter(self.current_scope))?;
+        let kept = self
+            .scope_entries
+            .iter()
+            .filter(|entry| filters.iter().any(|filter| filter.matches(entry)))
+            .count();
+        self.marked.clear();
+        let mut message = match removed {
+            1 => "Deleted 1 entry".to_string(),
+            n => format!("Deleted {} entries", n),
+        };
+        if kept > 0 {
+            message.push_str(&format!("; {} in read-only sources kept", kept));
+        }
+        self.message = Some(message);
+
+        if let Some(command) = &self.expanded {
+            if !self.scope_entries.iter().any(|entry| entry.command == *command) {
+                self.expanded = None;
+            }
+        }
+        let selected = self.selected_index;
+        self.update_filter();
+        self.selected_index = selected.min(self.filtered_entries.len().saturating_sub(1));
+        self.update_scroll();
+        Ok(())
+    }
+
+    /// Edit the file name of a pending export, writing it on Enter.
+    fn edit_export(&mut self, mut pending: PendingExport, key: KeyEvent) {
+        let chord = key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Enter => {
+                self.write_export(pending);
+                return;
+            }
+            KeyCode::Esc => return,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return,
+            KeyCode::Char(c) if !chord => pending.path.insert_char(c),
+            KeyCode::Backspace => pending.path.delete_back(),
+            KeyCode::Delete => pending.path.delete_forward(),
+            KeyCode::Left => pending.path.move_left(),
+            KeyCode::Right => pending.path.move_right(),
+            KeyCode::Home => pending.path.move_home(),
+            KeyCode::End => pending.path.move_end(),
+            _ => {}
+        }
+        self.pending_export = Some(pending);
+    }
+
+    /// Write a confirmed export, reporting how it went on the prompt line.
+    fn write_export(&mut self, pending: PendingExport) {
+        let name = pending.path.text().trim();
+        if name.is_empty() {
+            return;
+        }
+        let path = match name.strip_prefix("~/").zip(dirs::home_dir()) {
+            Some((rest, home)) => home.join(rest),
+            None => std::path::PathBuf::from(name),
+        };
+
+        let mut entries = pending.entries;
+        entries.sort_by_key(|entry| entry.timestamp);
+        let written = (|| -> Result<()> {
+            let mut file = io::BufWriter::new(export::create_output(&path)?);
+            export::write_entries(ExportFormat::for_path(&path), &entries, &mut file)?;
+            file.flush()?;
+            Ok(())
+        })();
+
+        self.message = Some(match written {
+            Ok(()) if entries.len() == 1 => format!("Exported 1 entry to {}", name),
+            Ok(()) => format!("Exported {} entries to {}", entries.len(), name),
+            Err(err) => format!("Export failed: {:#}", err),
+        });
+    }
+
+    /// Leave the occurrence list, reselecting the command it was opened from.
+    fn collapse(&mut self) {
+        let Some(command) = self.expanded.take() else {
+            return;
+        };
+        self.update_filter();
+        if let Some(index) = self.filtered_entries.iter().position(|entry| entry.command == command) {
+            self.selected_index = index;
+            self.update_scroll();
+        }
+    }
+
+    /// Split the terminal between header, list, preview pane and prompt.
+    ///
+    /// The preview pane is left out when the list would be squeezed below
+    /// [`MIN_LIST_ROWS`] rows or [`MIN_LIST_WIDTH`] columns to make room.
+    fn layout(&self, cols: u16, rows: u16) -> Layout {
+        // AIDEV-NOTE: one header line on top, the prompt on the bottom line
+        let header_lines = 1;
+        let body_rows = rows.saturating_sub(header_lines + 1) as usize;
+        let cols = cols as usize;
+        let hidden = Layout {
+            list_rows: body_rows,
+            list_width: cols,
+            preview: None,
+        };
+
+        match self.preview {
+            PreviewLayout::Hidden => hidden,
+            PreviewLayout::Bottom if body_rows < MIN_LIST_ROWS + MIN_PREVIEW_ROWS => hidden,
+            PreviewLayout::Bottom => {
+                // Room for a couple of command lines and the details, up to half the body
+                let height = (body_rows / 2).clamp(MIN_PREVIEW_ROWS, 13);
+                let list_rows = body_rows - height;
+                Layout {
+                    list_rows,
+                    list_width: cols,
+                    preview: Some(Pane {
+                        x: 0,
+                        y: header_lines + list_rows as u16,
+                        width: cols,
+                        height,
+                    }),
+                }
+            }
+            PreviewLayout::Right => {
+                let width = cols * 2 / 5;
+                let list_width = cols - width;
+                if list_width < MIN_LIST_WIDTH || width < MIN_PREVIEW_WIDTH {
+                    return hidden;
+                }
+                Layout {
+                    list_rows: body_rows,
+                    list_width,
+                    preview: Some(Pane {
+                        x: list_width as u16,
+                        y: header_lines,
+                        width,
+                        height: body_rows,
+                    }),
+                }
+            }
         }
     }
 
     fn update_scroll(&mut self) {
-        let (_, rows) = terminal::size().unwrap_or((80, 24));
-        let header_lines = 1; // Updated to match render function
-        let available_rows = rows.saturating_sub(header_lines + 1) as usize;
+        let (cols, rows) = self.viewport();
+        let available_rows = self.layout(cols, rows).list_rows;
         
         if available_rows == 0 {
             return;
         }
         
-        // AIDEV-NOTE: Atuin-style proactive scrolling at 35% threshold
-        let scroll_threshold = (available_rows as f32 * 0.35).max(1.0) as usize;
+        // AIDEV-NOTE: Atuin-style proactive scrolling, by default at a 35% threshold
+        let scroll_threshold = (available_rows as f64 * self.options.scroll_threshold).max(1.0) as usize;
         
         // Current position relative to scroll window
         let current_pos_in_window = self.selected_index.saturating_sub(self.scroll_offset);
@@ -274,43 +1158,65 @@ impl InteractiveSearcher {
     }
 
     fn render(&self) -> Result<()> {
-        let (cols, rows) = size()?;
+        let (cols, rows) = self.viewport();
         let mut stdout = stdout();
         
-        // Clear screen and move to top
-        execute!(stdout, terminal::Clear(terminal::ClearType::All))?;
+        // Clear the picker's area, which is the whole screen unless inline
+        execute!(stdout, self.move_to(0, 0), terminal::Clear(terminal::ClearType::FromCursorDown))?;
         
-        // AIDEV-NOTE: calculate layout with single header line (no horizontal separator)
+        let layout = self.layout(cols, rows);
         let header_lines = 1;
-        let available_rows = rows.saturating_sub(header_lines + 1) as usize;
+        let available_rows = layout.list_rows;
         
         // Split header: scope indicator on left, help+rehash on right
-        let scope_prompt = match self.current_scope {
-            SearchScope::Global => style("[ GLOBAL ]").cyan().bold(),
-            SearchScope::Session => style("[ SESSION ]").yellow().bold(),
-            SearchScope::Local => style("[ DIRECTORY ]").green().bold(),
+        let scope_label = match self.current_scope {
+            SearchScope::Global => "[ GLOBAL ]",
+            SearchScope::Session => "[ SESSION ]",
+            SearchScope::Local => "[ DIRECTORY ]",
+            SearchScope::Host => "[ HOST ]",
+            SearchScope::Project => "[ PROJECT ]",
         };
+        let styles = &self.options.styles;
+        let scope_prompt = styles.scope(self.current_scope).apply_to(scope_label);
         
-        let help_text = style("F1-F3: Scope | Tab: Cycle").black().bright();
-        let rehash_text = style("  rehash").white();
-        let right_content = format!("{}{}", help_text, rehash_text);
-        
-        // AIDEV-NOTE: calculate padding between left and right parts
-        let scope_display_width = match self.current_scope {
-            SearchScope::Global => "[ GLOBAL ]".len(),
-            SearchScope::Session => "[ SESSION ]".len(), 
-            SearchScope::Local => "[ DIRECTORY ]".len(),
-        };
-        let right_display_width = "F1-F3: Scope | Tab: Cycle  rehash".len();
-        
-        let middle_padding = if cols as usize > scope_display_width + right_display_width {
-            " ".repeat(cols as usize - scope_display_width - right_display_width)
+        // Which rows are listed: one per command, every entry, or the runs of one command
+        let view = if self.expanded.is_some() {
+            "runs"
+        } else if self.searcher.dedup() {
+            "unique"
         } else {
-            " ".to_string()
+            "all"
         };
+        let mut mode_label = format!(" {}/{}", self.searcher.mode().label(), view);
+        if !self.marked.is_empty() {
+            mode_label.push_str(&format!(" [{} marked]", self.marked.len()));
+        }
+        if self.normal_mode {
+            mode_label.push_str(" [normal]");
+        }
+        let mode_text = styles.header.apply_to(mode_label.as_str());
+
+        // AIDEV-NOTE: calculate padding between left and right parts, dropping
+        // the help text on terminals too narrow for it
+        let cols = cols as usize;
+        let left_width = display_width(scope_label) + display_width(&mode_label);
+        let rehash_label = "  rehash";
+        let help = self.options.keymap.help(self.normal_mode);
+        let (right_content, right_width) =
+            if left_width + display_width(&help) + display_width(rehash_label) < cols {
+                (
+                    format!("{}{}", styles.help.apply_to(&help), styles.header.apply_to(rehash_label)),
+                    display_width(&help) + display_width(rehash_label),
+                )
+            } else if left_width + display_width(rehash_label) < cols {
+                (styles.header.apply_to(rehash_label).to_string(), display_width(rehash_label))
+            } else {
+                (String::new(), 0)
+            };
+        let middle_padding = " ".repeat(cols.saturating_sub(left_width + right_width));
         
-        execute!(stdout, cursor::MoveTo(0, 0))?;
-        println!("{}{}{}\r", scope_prompt, middle_padding, right_content);
+        execute!(stdout, self.move_to(0, 0))?;
+        println!("{}{}{}{}\r", scope_prompt, mode_text, middle_padding, right_content);
 
         // AIDEV-NOTE: show entries in chronological order (oldest first) so newest appears at bottom near prompt
         let start_idx = self.scroll_offset;
@@ -321,50 +1227,733 @@ impl InteractiveSearcher {
                 let is_selected = entry_idx == self.selected_index;
                 let row = header_lines + display_row as u16;
                 
-                execute!(stdout, cursor::MoveTo(0, row))?;
+                execute!(stdout, self.move_to(0, row))?;
                 
+                // Mark column
+                let mark = if self.is_marked(entry) { "*" } else { " " };
+                let mark_colored = if is_selected {
+                    styles.selected_mark.apply_to(mark)
+                } else {
+                    styles.mark.apply_to(mark)
+                };
+
                 // Format time column
                 let time_str = format!("{:>8}", format_relative_time(&entry.timestamp));
                 let time_colored = if is_selected {
-                    style(time_str).black().on_white()
+                    styles.selected_text.apply_to(time_str)
                 } else {
-                    style(time_str).blue()
+                    styles.time.apply_to(time_str)
                 };
-                
-                // AIDEV-NOTE: calculate available space for command
-                let time_width = 10;
-                let available_cmd_width = cols.saturating_sub(time_width + 2) as usize;
-                
-                // Truncate command if too long
-                let command = if entry.command.len() > available_cmd_width {
-                    format!("{}…", &entry.command[..available_cmd_width.saturating_sub(1)])
+
+                // Duration column, blank for entries recorded without start/end
+                let duration_str = format!(
+                    "{:>7}",
+                    entry.duration().map(format_duration).unwrap_or_default()
+                );
+                let duration_colored = if is_selected {
+                    styles.selected_text.apply_to(duration_str)
                 } else {
-                    entry.command.clone()
+                    styles.duration.apply_to(duration_str)
                 };
                 
-                let command_colored = if is_selected {
-                    style(format!(" {}", command)).black().on_white().bold()
+                // Run count, blank for commands that ran once
+                let runs_str = if entry.count > 1 {
+                    format!("{:>6}", format!("{}x", entry.count))
+                } else {
+                    " ".repeat(6)
+                };
+                let runs_colored = if is_selected {
+                    styles.selected_text.apply_to(runs_str)
+                } else {
+                    styles.runs.apply_to(runs_str)
+                };
+
+                // Last exit status, blank on success
+                let exit_str = if entry.exit_code != 0 {
+                    format!("{:>5}", entry.exit_code)
+                } else {
+                    " ".repeat(5)
+                };
+                let exit_colored = if is_selected {
+                    styles.selected_error.apply_to(exit_str)
                 } else {
-                    style(format!(" {}", command)).white()
+                    styles.error.apply_to(exit_str)
                 };
                 
-                print!("{}{}\r", time_colored, command_colored);
+                // AIDEV-NOTE: calculate available space for command
+                let mark_width = 1;
+                let time_width = 10;
+                let duration_width = 7;
+                let runs_width = 6;
+                let exit_width = 5;
+                let available_cmd_width = layout
+                    .list_width
+                    .saturating_sub(mark_width + time_width + duration_width + runs_width + exit_width + 2);
+                
+                let matched = self
+                    .highlight
+                    .as_ref()
+                    .map(|pattern| self.searcher.match_indices(pattern, &entry.command))
+                    .unwrap_or_default();
+                let command_colored = styled_command(&entry.command, &matched, available_cmd_width, is_selected, styles);
+                
+                print!(
+                    "{}{}{}{}{}{}\r",
+                    mark_colored, time_colored, duration_colored, runs_colored, exit_colored, command_colored
+                );
             }
         }
         
+        if let Some(pane) = &layout.preview {
+            self.render_preview(pane)?;
+        }
+        
         // Input prompt at bottom
         let prompt_row = rows.saturating_sub(1);
-        execute!(stdout, cursor::MoveTo(0, prompt_row))?;
+        execute!(stdout, self.move_to(0, prompt_row))?;
         
+        // AIDEV-NOTE: scroll long queries horizontally so the cursor stays visible
+        let prompt_width = 2;
+        let query_width = cols.saturating_sub(prompt_width + 1);
+        let mut hidden_width = 0;
+        let mut visible_query = self.query.text();
+        while self.query.cursor_width() - hidden_width > query_width {
+            let Some(first) = visible_query.graphemes(true).next() else {
+                break;
+            };
+            hidden_width += display_width(first);
+            visible_query = &visible_query[first.len()..];
+        }
+        let (shown, _) = fit_graphemes(visible_query, query_width + 1);
+        let shown: String = shown.into_iter().map(|(_, grapheme)| grapheme).collect();
+
+        let placeholder = "Type to search...";
+        let query_display_width = if self.query.is_empty() {
+            display_width(placeholder)
+        } else {
+            display_width(&shown)
+        };
         let query_display = if self.query.is_empty() {
-            style("Type to search...").blue().italic()
+            styles.placeholder.apply_to(placeholder.to_string())
         } else {
-            style(self.query.as_str()).white().bold()
+            styles.query.apply_to(shown)
         };
         
-        print!("{} {}", style(">").cyan().bold(), query_display);
+        if let Some(pending) = &self.pending_delete {
+            let count = pending.entries.len();
+            let question = match (pending.single, count) {
+                (false, 1) => format!("Delete all {} runs of this command? [y/n] ", pending.runs),
+                (false, _) => format!("Delete all {} runs of {} marked commands? [y/n] ", pending.runs, count),
+                (true, 1) if pending.runs > 1 => {
+                    format!("Delete this entry? [y]es, [a]ll {} runs, [n]o ", pending.runs)
+                }
+                (true, 1) => "Delete this entry? [y/n] ".to_string(),
+                (true, _) => format!(
+                    "Delete {} marked entries? [y]es, [a]ll {} runs of their commands, [n]o ",
+                    count, pending.runs
+                ),
+            };
+            let question = truncate_to_width(&question, cols.saturating_sub(1));
+            print!("{}", styles.warning.apply_to(&question));
+            execute!(stdout, self.move_to(display_width(&question) as u16, prompt_row))?;
+            stdout.flush()?;
+            return Ok(());
+        }
+
+        if let Some(pending) = &self.pending_export {
+            let question = match pending.entries.len() {
+                1 => "Export this entry to: ".to_string(),
+                n => format!("Export {} marked entries to: ", n),
+            };
+            let question = truncate_to_width(&question, cols.saturating_sub(1));
+            let room = cols.saturating_sub(display_width(&question) + 1);
+            print!("{}{}", styles.warning.apply_to(&question), truncate_to_width(pending.path.text(), room));
+            let cursor_column = display_width(&question) + pending.path.cursor_width().min(room);
+            execute!(stdout, self.move_to(cursor_column as u16, prompt_row))?;
+            stdout.flush()?;
+            return Ok(());
+        }
+
+        print!("{} {}", styles.prompt.apply_to(">"), query_display);
+        if let Some(message) = &self.message {
+            if prompt_width + query_display_width + display_width(message) + 2 <= cols {
+                execute!(stdout, self.move_to((cols - display_width(message)) as u16, prompt_row))?;
+                print!("{}", styles.message.apply_to(message));
+            }
+        }
+        let cursor_column = prompt_width + self.query.cursor_width() - hidden_width;
+        execute!(stdout, self.move_to(cursor_column as u16, prompt_row))?;
         
         stdout.flush()?;
         Ok(())
     }
+
+    /// Draw the selected entry's full command and metadata into `pane`.
+    fn render_preview(&self, pane: &Pane) -> Result<()> {
+        let mut stdout = stdout();
+        let styles = &self.options.styles;
+
+        // Separator along the edge facing the list
+        let vertical = pane.x > 0;
+        if vertical {
+            for row in 0..pane.height {
+                execute!(stdout, self.move_to(pane.x, pane.y + row as u16))?;
+                print!("{}", styles.border.apply_to("│"));
+            }
+        } else {
+            execute!(stdout, self.move_to(pane.x, pane.y))?;
+            print!("{}", styles.border.apply_to("─".repeat(pane.width)));
+        }
+        let (x, y, width, height) = if vertical {
+            (pane.x + 2, pane.y, pane.width.saturating_sub(2), pane.height)
+        } else {
+            (pane.x, pane.y + 1, pane.width, pane.height.saturating_sub(1))
+        };
+
+        let Some(entry) = self.filtered_entries.get(self.selected_index) else {
+            return Ok(());
+        };
+
+        // Runs of this command in the current scope, whichever view is active
+        let runs: u32 = self
+            .scope_entries
+            .iter()
+            .filter(|other| other.command == entry.command)
+            .map(|other| other.count)
+            .sum();
+
+        let details = preview_details(entry, runs);
+        // AIDEV-NOTE: details always fit; the command gets whatever rows remain
+        let command_rows = height.saturating_sub(details.len() + 1).max(1);
+        let mut lines: Vec<String> = preview_command(&entry.command, width, command_rows)
+            .into_iter()
+            .map(|line| styles.preview.apply_to(line).to_string())
+            .collect();
+        lines.push(String::new());
+        let label_width = 10;
+        for (label, value) in details {
+            let value = truncate_to_width(&value, width.saturating_sub(label_width));
+            let value = match label {
+                "Exit" if entry.exit_code != 0 => styles.error.apply_to(value).to_string(),
+                _ => styles.text.apply_to(value).to_string(),
+            };
+            lines.push(format!("{}{}", styles.label.apply_to(format!("{:<label_width$}", label)), value));
+        }
+
+        for (row, line) in lines.into_iter().take(height).enumerate() {
+            execute!(stdout, self.move_to(x, y + row as u16))?;
+            print!("{}", line);
+        }
+        Ok(())
+    }
+}
+
+/// The labelled details listed under the command in the preview pane.
+fn preview_details(entry: &HistoryEntry, runs: u32) -> Vec<(&'static str, String)> {
+    let local_time = entry.timestamp.with_timezone(&Local);
+    let mut details = vec![
+        (
+            "Time",
+            format!(
+                "{} ({})",
+                local_time.format("%Y-%m-%d %H:%M:%S"),
+                format_relative_time(&entry.timestamp)
+            ),
+        ),
+        ("Directory", entry.directory.clone()),
+        ("Exit", entry.exit_code.to_string()),
+        ("Session", entry.session_id.clone()),
+    ];
+    if let Some(duration) = entry.duration() {
+        details.push(("Duration", format_duration(duration)));
+    }
+    details.push(("Runs", runs.to_string()));
+    if let Some(hostname) = &entry.hostname {
+        details.push(("Host", hostname.clone()));
+    }
+    if let Some(branch) = &entry.branch {
+        details.push(("Branch", branch.clone()));
+    }
+    details
+}
+
+/// `command` wrapped to `width` columns, with an ellipsis after the last of
+/// `rows` lines if it doesn't fit.
+fn preview_command(command: &str, width: usize, rows: usize) -> Vec<String> {
+    let mut lines = wrap_to_width(command, width);
+    if lines.len() > rows {
+        lines.truncate(rows);
+        if let Some(last) = lines.last_mut() {
+            *last = truncate_to_width(&format!("{} …", last), width);
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit(text: &str, max_width: usize) -> (String, bool) {
+        let (graphemes, truncated) = fit_graphemes(text, max_width);
+        (graphemes.into_iter().map(|(_, g)| g).collect(), truncated)
+    }
+
+    #[test]
+    fn ascii_is_cut_leaving_room_for_the_ellipsis() {
+        assert_eq!(fit("git status", 20), ("git status".to_string(), false));
+        assert_eq!(fit("git status", 10), ("git status".to_string(), false));
+        assert_eq!(fit("git status", 6), ("git s".to_string(), true));
+    }
+
+    #[test]
+    fn accented_characters_count_as_one_column() {
+        // Precomposed and combining forms both take one column per letter
+        assert_eq!(fit("echo café", 9), ("echo café".to_string(), false));
+        assert_eq!(fit("echo cafe\u{301}", 9), ("echo cafe\u{301}".to_string(), false));
+        assert_eq!(fit("echo cafe\u{301}!", 9), ("echo caf".to_string(), true));
+        assert_eq!(fit("échö ñ", 3), ("éc".to_string(), true));
+    }
+
+    #[test]
+    fn emoji_are_two_columns_and_never_split() {
+        assert_eq!(display_width("🚀"), 2);
+        assert_eq!(fit("echo 🚀🚀", 9), ("echo 🚀🚀".to_string(), false));
+        // 🚀 would end at column 7, past the 6 left before the ellipsis
+        assert_eq!(fit("echo 🚀🚀", 7), ("echo ".to_string(), true));
+        // A ZWJ family and a flag stay whole graphemes
+        let (graphemes, _) = fit_graphemes("👨\u{200d}👩\u{200d}👧 🇯🇵", 20);
+        assert_eq!(graphemes[0], (0..5, "👨\u{200d}👩\u{200d}👧"));
+        assert_eq!(graphemes[2], (6..8, "🇯🇵"));
+    }
+
+    #[test]
+    fn cjk_is_two_columns_per_character() {
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(fit("ls 日本語", 9), ("ls 日本語".to_string(), false));
+        assert_eq!(fit("ls 日本語", 8), ("ls 日本".to_string(), true));
+        assert_eq!(fit("ls 日本語", 7), ("ls 日".to_string(), true));
+    }
+
+    #[test]
+    fn control_characters_are_flattened() {
+        assert_eq!(fit("for x\n  do\tls", 20), ("for x   do ls".to_string(), false));
+        // `\r\n` is one grapheme covering two chars
+        let (graphemes, _) = fit_graphemes("a\r\nb", 10);
+        assert_eq!(graphemes, vec![(0..1, "a"), (1..3, " "), (3..4, "b")]);
+    }
+
+    #[test]
+    fn highlighted_truncated_command_stays_within_width() {
+        let command = "grep 日本語 ファイル.txt";
+        // 語 would need columns 10-11 but only 10 are free before the ellipsis
+        let rendered = styled_command(command, &[5, 6], 11, false, &Styles::default());
+        let plain = console::strip_ansi_codes(&rendered);
+        assert_eq!(plain, " grep 日本…");
+        assert_eq!(display_width(&plain), 1 + 10);
+    }
+
+    #[test]
+    fn wrapping_keeps_line_breaks_and_wide_characters_whole() {
+        assert_eq!(wrap_to_width("for f in *; do\n\techo $f\ndone", 20), ["for f in *; do", " echo $f", "done"]);
+        assert_eq!(wrap_to_width("echo 日本語", 7), ["echo 日", "本語"]);
+        assert_eq!(wrap_to_width("🚀🚀🚀", 3), ["🚀", "🚀", "🚀"]);
+        assert_eq!(truncate_to_width("echo 日本語", 8), "echo 日…");
+    }
+
+    fn entry(command: &str, seconds: i64) -> HistoryEntry {
+        HistoryEntry::test(command, "/src", 0, chrono::DateTime::from_timestamp(seconds, 0).unwrap())
+    }
+
+    /// A store holding make, ls, make and git status, oldest first.
+    fn storage(dir: &std::path::Path) -> Storage {
+        let storage = Storage::new(Some(dir.join("history.jsonl").to_string_lossy().to_string()), vec![]).unwrap();
+        for (seconds, command) in [(1, "make"), (2, "ls"), (3, "make"), (4, "git status")] {
+            storage.add_entry(entry(command, 1_700_000_000 + seconds)).unwrap();
+        }
+        storage
+    }
+
+    fn open_picker(storage: &Storage, dedup: bool) -> InteractiveSearcher<'_> {
+        let context = ScopeContext {
+            current_dir: "/src".to_string(),
+            session_id: "s1".to_string(),
+            hostname: None,
+            project_root: None,
+        };
+        let ranker = Ranker::new(crate::ranking::RankingWeights::default(), "/src");
+        let searcher = FuzzySearcher::new(MatchMode::Fuzzy, ranker, dedup);
+        InteractiveSearcher::new_with_prefix(storage, SearchScope::Global, searcher, context, None).unwrap()
+    }
+
+    fn perform(picker: &mut InteractiveSearcher, actions: &[Action]) {
+        for &action in actions {
+            assert!(picker.perform(action).unwrap().is_continue(), "{:?}", action);
+        }
+    }
+
+    fn accepted(picker: &InteractiveSearcher) -> String {
+        picker.selection(AcceptAction::Execute).unwrap().command
+    }
+
+    #[test]
+    fn scopes_are_read_from_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        storage.add_entry(HistoryEntry { session_id: "s2".to_string(), ..entry("vim", 1_700_000_005) }).unwrap();
+        let mut picker = open_picker(&storage, true);
+        let listed = |picker: &InteractiveSearcher| -> Vec<String> {
+            picker.filtered_entries.iter().map(|entry| entry.command.clone()).collect()
+        };
+        assert_eq!(listed(&picker), ["ls", "make", "git status", "vim"]);
+
+        perform(&mut picker, &[Action::ScopeSession]);
+        assert_eq!(listed(&picker), ["ls", "make", "git status"]);
+
+        // Switching scope reads the store again
+        storage.add_entry(entry("htop", 1_700_000_006)).unwrap();
+        perform(&mut picker, &[Action::ScopeGlobal]);
+        assert_eq!(listed(&picker), ["ls", "make", "git status", "vim", "htop"]);
+    }
+
+    #[test]
+    fn filter_only_queries_select_the_newest_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        storage.add_entry(HistoryEntry { exit_code: 2, ..entry("cargo test", 1_700_000_005) }).unwrap();
+
+        for dedup in [false, true] {
+            let mut picker = open_picker(&storage, dedup);
+            picker.query.insert_str("exit:0");
+            picker.update_filter();
+            assert_eq!(picker.selected_index, picker.filtered_entries.len() - 1);
+            assert_eq!(accepted(&picker), "git status", "dedup: {}", dedup);
+        }
+    }
+
+    #[test]
+    fn heights_parse_lines_and_percentages() {
+        assert_eq!(Height::parse("20").unwrap(), Height::Lines(20));
+        assert_eq!(Height::parse(" 40% ").unwrap(), Height::Percent(40));
+        assert_eq!(Height::parse("full").unwrap(), Height::Full);
+        // A whole terminal or more is the full screen
+        assert_eq!(Height::parse("100%").unwrap(), Height::Full);
+        assert_eq!(Height::parse("150%").unwrap(), Height::Full);
+
+        for zero in ["0", "0%"] {
+            assert_eq!(Height::parse(zero).unwrap_err().to_string(), "height must be greater than zero");
+        }
+        for garbage in ["", "tall", "%", "-5", "2.5", "40%%", "70000"] {
+            let err = Height::parse(garbage).unwrap_err().to_string();
+            assert!(err.starts_with("invalid height"), "{}: {}", garbage, err);
+        }
+    }
+
+    #[test]
+    fn height_rows_fit_the_terminal() {
+        assert_eq!(Height::Full.rows(30), 30);
+        assert_eq!(Height::Lines(20).rows(30), 20);
+        assert_eq!(Height::Percent(40).rows(30), 12);
+        // The line the picker started on stays visible
+        assert_eq!(Height::Lines(100).rows(30), 29);
+        assert_eq!(Height::Percent(99).rows(30), 29);
+        // Never fewer than header, one entry and prompt, unless the terminal is smaller
+        assert_eq!(Height::Lines(1).rows(30), 3);
+        assert_eq!(Height::Percent(1).rows(30), 3);
+        assert_eq!(Height::Lines(20).rows(2), 1);
+    }
+
+    #[test]
+    fn height_round_trips_through_the_config() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Ui {
+            height: Height,
+        }
+
+        for (text, height) in [
+            ("height = 20\n", Height::Lines(20)),
+            ("height = \"40%\"\n", Height::Percent(40)),
+            ("height = \"full\"\n", Height::Full),
+        ] {
+            let ui: Ui = toml::from_str(text).unwrap();
+            assert_eq!(ui, Ui { height });
+            assert_eq!(toml::to_string(&ui).unwrap(), text);
+        }
+        assert_eq!(toml::from_str::<Ui>("height = \"20\"").unwrap(), Ui { height: Height::Lines(20) });
+        let err = toml::from_str::<Ui>("height = \"tall\"").unwrap_err().to_string();
+        assert!(err.contains("invalid height 'tall'"), "{}", err);
+    }
+
+    #[test]
+    fn output_file_holds_the_action_then_the_command() {
+        let selection = |command: &str, action| Selection { command: command.to_string(), action };
+        assert_eq!(selection("make", AcceptAction::Execute).to_output_file(), "execute\nmake");
+        assert_eq!(selection("git status\nmake", AcceptAction::Edit).to_output_file(), "edit\ngit status\nmake");
+
+        // Split the way the shell widgets do
+        let contents = selection("for f in *; do\n  echo $f\ndone", AcceptAction::Edit).to_output_file();
+        let (action, command) = contents.split_once('\n').unwrap();
+        assert_eq!((action, command), ("edit", "for f in *; do\n  echo $f\ndone"));
+
+        // Enter runs, Tab edits
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        let mut picker = open_picker(&storage, true);
+        for (key, expected) in [(Action::Accept, "execute\ngit status"), (Action::Edit, "edit\ngit status")] {
+            let ControlFlow::Break(Some(selected)) = picker.perform(key).unwrap() else {
+                panic!("{:?} did not accept", key);
+            };
+            assert_eq!(selected.to_output_file(), expected);
+        }
+    }
+
+    fn remaining(storage: &Storage) -> Vec<String> {
+        storage.get_all_entries().unwrap().into_iter().map(|entry| entry.command).collect()
+    }
+
+    #[test]
+    fn deleting_asks_for_one_run_or_every_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        let mut picker = open_picker(&storage, false);
+
+        // Anything but y or a keeps the entry
+        for answer in [KeyCode::Char('n'), KeyCode::Esc] {
+            perform(&mut picker, &[Action::DeleteEntry]);
+            let pending = picker.pending_delete.take().unwrap();
+            picker.confirm_delete(pending, answer).unwrap();
+        }
+        assert_eq!(remaining(&storage), ["make", "ls", "make", "git status"]);
+
+        // `a` takes every run of the selected command
+        perform(&mut picker, &[Action::Up, Action::DeleteEntry]);
+        let pending = picker.pending_delete.take().unwrap();
+        assert!(pending.single);
+        assert_eq!(pending.runs, 2);
+        picker.confirm_delete(pending, KeyCode::Char('a')).unwrap();
+        assert_eq!(picker.message.as_deref(), Some("Deleted 2 entries"));
+        assert_eq!(remaining(&storage), ["ls", "git status"]);
+
+        // `y` only the selected run
+        storage.add_entry(entry("ls", 1_700_000_005)).unwrap();
+        let mut picker = open_picker(&storage, false);
+        perform(&mut picker, &[Action::DeleteEntry]);
+        let pending = picker.pending_delete.take().unwrap();
+        picker.confirm_delete(pending, KeyCode::Char('y')).unwrap();
+        assert_eq!(picker.message.as_deref(), Some("Deleted 1 entry"));
+        assert_eq!(remaining(&storage), ["ls", "git status"]);
+        assert_eq!(picker.filtered_entries.len(), 2);
+    }
+
+    #[test]
+    fn read_sources_are_not_deleted_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = dir.path().join("other.jsonl");
+        std::fs::write(&other, serde_json::to_string(&entry("make", 1_700_000_000)).unwrap() + "\n").unwrap();
+        let storage = Storage::new(
+            Some(dir.path().join("history.jsonl").to_string_lossy().to_string()),
+            vec![other.to_string_lossy().to_string()],
+        )
+        .unwrap();
+        storage.add_entry(entry("make", 1_700_000_001)).unwrap();
+
+        let mut picker = open_picker(&storage, true);
+        perform(&mut picker, &[Action::DeleteEntry]);
+        let pending = picker.pending_delete.take().unwrap();
+        assert!(!pending.single);
+        assert_eq!(pending.runs, 1);
+        picker.confirm_delete(pending, KeyCode::Char('y')).unwrap();
+        assert_eq!(picker.message.as_deref(), Some("Deleted 1 entry; 1 in read-only sources kept"));
+        assert_eq!(accepted(&picker), "make");
+        assert_eq!(std::fs::read_to_string(&other).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn marked_commands_are_accepted_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        let mut picker = open_picker(&storage, true);
+        assert_eq!(accepted(&picker), "git status");
+
+        // Marking moves up the list: git status, then make
+        perform(&mut picker, &[Action::ToggleMark, Action::MarkOrSpace]);
+        assert_eq!(accepted(&picker), "git status\nmake");
+        picker.options.join = JoinStyle::And;
+        assert_eq!(accepted(&picker), "git status && make");
+
+        // Unmarking keeps the order of the rest
+        perform(&mut picker, &[Action::Bottom, Action::ToggleMark]);
+        assert_eq!(accepted(&picker), "make");
+
+        perform(&mut picker, &[Action::ToggleMarkAll]);
+        assert_eq!(accepted(&picker), "make && ls && git status");
+        perform(&mut picker, &[Action::ToggleMarkAll]);
+        assert!(picker.marked.is_empty());
+
+        // Space types once there is a query
+        picker.query.insert_str("git");
+        perform(&mut picker, &[Action::MarkOrSpace]);
+        assert!(picker.marked.is_empty());
+        assert_eq!(picker.query.text(), "git ");
+    }
+
+    #[test]
+    fn marked_entries_are_deleted_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+
+        // Every run is listed: delete the newest make and ls, and only those
+        let mut picker = open_picker(&storage, false);
+        perform(&mut picker, &[Action::Up, Action::ToggleMark, Action::ToggleMark, Action::DeleteEntry]);
+        let pending = picker.pending_delete.take().unwrap();
+        assert!(pending.single);
+        assert_eq!(pending.runs, 3);
+        picker.confirm_delete(pending, KeyCode::Char('y')).unwrap();
+        assert_eq!(picker.message.as_deref(), Some("Deleted 2 entries"));
+        assert!(picker.marked.is_empty());
+        assert_eq!(remaining(&storage), ["make", "git status"]);
+
+        // `n` keeps everything
+        perform(&mut picker, &[Action::ToggleMarkAll, Action::DeleteEntry]);
+        let pending = picker.pending_delete.take().unwrap();
+        picker.confirm_delete(pending, KeyCode::Char('n')).unwrap();
+        assert_eq!(remaining(&storage), ["make", "git status"]);
+
+        // Collapsed rows stand for every run of their command
+        storage.add_entry(entry("make", 1_700_000_010)).unwrap();
+        let mut collapsed = open_picker(&storage, true);
+        assert_eq!(accepted(&collapsed), "make");
+        perform(&mut collapsed, &[Action::ToggleMark, Action::DeleteEntry]);
+        let pending = collapsed.pending_delete.take().unwrap();
+        assert!(!pending.single);
+        assert_eq!(pending.runs, 2);
+        collapsed.confirm_delete(pending, KeyCode::Char('y')).unwrap();
+        assert_eq!(remaining(&storage), ["git status"]);
+    }
+
+    #[test]
+    fn marked_entries_are_exported_to_the_named_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        let mut picker = open_picker(&storage, false);
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        // Marked newest first, written oldest first
+        perform(&mut picker, &[Action::ToggleMark, Action::ToggleMark, Action::Export]);
+        let mut pending = picker.pending_export.take().unwrap();
+        let path = dir.path().join("script.sh");
+        pending.path.insert_str(&path.to_string_lossy());
+        picker.edit_export(pending, key(KeyCode::Enter));
+
+        assert!(picker.pending_export.is_none());
+        assert_eq!(picker.message, Some(format!("Exported 2 entries to {}", path.display())));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "#1700000003\nmake\n#1700000004\ngit status\n");
+        assert_eq!(picker.marked.len(), 2);
+
+        // Typed names can be edited, and Esc leaves without writing
+        perform(&mut picker, &[Action::Export]);
+        for code in [KeyCode::Char('x'), KeyCode::Char('y'), KeyCode::Backspace] {
+            let pending = picker.pending_export.take().unwrap();
+            picker.edit_export(pending, key(code));
+        }
+        assert_eq!(picker.pending_export.as_ref().unwrap().path.text(), "x");
+        let pending = picker.pending_export.take().unwrap();
+        picker.edit_export(pending, key(KeyCode::Esc));
+        assert!(picker.pending_export.is_none());
+        assert!(!std::path::Path::new("x").exists());
+    }
+
+    fn layout(picker: &mut InteractiveSearcher, preview: PreviewLayout, cols: u16, rows: u16) -> Layout {
+        picker.preview = preview;
+        picker.layout(cols, rows)
+    }
+
+    #[test]
+    fn layout_makes_room_for_the_preview() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        let mut picker = open_picker(&storage, true);
+
+        // Everything but the header and prompt lines
+        let hidden = layout(&mut picker, PreviewLayout::Hidden, 100, 30);
+        assert_eq!((hidden.list_rows, hidden.list_width), (28, 100));
+        assert!(hidden.preview.is_none());
+
+        // Half the body, up to 13 rows, below the list
+        let bottom = layout(&mut picker, PreviewLayout::Bottom, 100, 30);
+        assert_eq!((bottom.list_rows, bottom.list_width), (15, 100));
+        let pane = bottom.preview.unwrap();
+        assert_eq!((pane.x, pane.y, pane.width, pane.height), (0, 16, 100, 13));
+        let bottom = layout(&mut picker, PreviewLayout::Bottom, 100, 12);
+        assert_eq!(bottom.list_rows, 5);
+        let pane = bottom.preview.unwrap();
+        assert_eq!((pane.y, pane.height), (6, 5));
+
+        // Two fifths of the width, beside the list
+        let right = layout(&mut picker, PreviewLayout::Right, 100, 30);
+        assert_eq!((right.list_rows, right.list_width), (28, 60));
+        let pane = right.preview.unwrap();
+        assert_eq!((pane.x, pane.y, pane.width, pane.height), (60, 1, 40, 28));
+    }
+
+    #[test]
+    fn preview_is_hidden_on_small_terminals() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path());
+        let mut picker = open_picker(&storage, true);
+
+        // The list keeps 40 columns
+        let right = layout(&mut picker, PreviewLayout::Right, 65, 30);
+        assert!(right.preview.is_none());
+        assert_eq!(right.list_width, 65);
+        assert_eq!(layout(&mut picker, PreviewLayout::Right, 66, 30).list_width, 40);
+
+        // And four rows
+        let bottom = layout(&mut picker, PreviewLayout::Bottom, 100, 9);
+        assert!(bottom.preview.is_none());
+        assert_eq!(bottom.list_rows, 7);
+        let bottom = layout(&mut picker, PreviewLayout::Bottom, 100, 10);
+        assert_eq!((bottom.list_rows, bottom.preview.unwrap().height), (4, 4));
+    }
+
+    #[test]
+    fn preview_details_show_what_the_entry_recorded() {
+        let labels = |details: &[(&'static str, String)]| -> Vec<&'static str> {
+            details.iter().map(|(label, _)| *label).collect()
+        };
+
+        let bare = preview_details(&entry("make", 1_700_000_000), 1);
+        assert_eq!(labels(&bare), ["Time", "Directory", "Exit", "Session", "Runs"]);
+        let local = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().with_timezone(&Local);
+        assert!(bare[0].1.starts_with(&local.format("%Y-%m-%d %H:%M:%S (").to_string()), "{}", bare[0].1);
+        assert_eq!(bare[1..], [
+            ("Directory", "/src".to_string()),
+            ("Exit", "0".to_string()),
+            ("Session", "s1".to_string()),
+            ("Runs", "1".to_string()),
+        ]);
+
+        let full = HistoryEntry {
+            exit_code: 2,
+            duration_ms: Some(185_000),
+            hostname: Some("laptop".to_string()),
+            branch: Some("main".to_string()),
+            ..entry("make", 1_700_000_000)
+        };
+        assert_eq!(preview_details(&full, 3)[2..], [
+            ("Exit", "2".to_string()),
+            ("Session", "s1".to_string()),
+            ("Duration", "3m05s".to_string()),
+            ("Runs", "3".to_string()),
+            ("Host", "laptop".to_string()),
+            ("Branch", "main".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn preview_command_wraps_into_the_rows_left() {
+        assert_eq!(preview_command("make test", 20, 3), ["make test"]);
+        assert_eq!(preview_command("for f in *; do\n  echo $f\ndone", 20, 3), ["for f in *; do", "  echo $f", "done"]);
+        assert_eq!(preview_command("for f in *; do\n  echo $f\ndone", 20, 2), ["for f in *; do", "  echo $f …"]);
+    }
 }
@@ -0,0 +1,102 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::history::HistoryEntry;
+
+/// Which entries `rehash compact` should drop.
+#[derive(Debug, Clone, Default)]
+pub struct CompactPolicy {
+    /// Keep only the most recent N entries
+    pub keep: Option<usize>,
+    /// Drop entries older than this
+    pub older_than: Option<Duration>,
+    /// Collapse identical commands run in the same directory
+    pub dedup: bool,
+}
+
+impl CompactPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep.is_none() && self.older_than.is_none() && !self.dedup
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CompactReport {
+    pub before: usize,
+    pub after: usize,
+    pub expired: usize,
+    pub duplicates: usize,
+    pub over_limit: usize,
+}
+
+impl CompactReport {
+    pub fn removed(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+/// Apply `policy` to `entries` (oldest first), returning the survivors in the
+/// same order.
+///
+/// AIDEV-NOTE: age is applied first, then dedup, then the count limit, so
+/// `--keep` counts distinct commands when combined with `--dedup`.
+pub fn compact_entries(
+    entries: Vec<HistoryEntry>,
+    policy: &CompactPolicy,
+    now: DateTime<Utc>,
+) -> (Vec<HistoryEntry>, CompactReport) {
+    let mut report = CompactReport {
+        before: entries.len(),
+        ..CompactReport::default()
+    };
+    let mut entries = entries;
+
+    if let Some(max_age) = policy.older_than {
+        // A cutoff before the earliest representable time expires nothing
+        if let Some(cutoff) = now.checked_sub_signed(max_age) {
+            entries.retain(|entry| entry.timestamp >= cutoff);
+        }
+        report.expired = report.before - entries.len();
+    }
+
+    if policy.dedup {
+        let before = entries.len();
+        entries = dedup_entries(entries);
+        report.duplicates = before - entries.len();
+    }
+
+    if let Some(keep) = policy.keep {
+        if entries.len() > keep {
+            report.over_limit = entries.len() - keep;
+            entries.drain(..report.over_limit);
+        }
+    }
+
+    report.after = entries.len();
+    (entries, report)
+}
+
+/// Keep the newest entry for each (directory, command), adding up the runs of
+/// the ones dropped into its `count`.
+fn dedup_entries(entries: Vec<HistoryEntry>) -> Vec<HistoryEntry> {
+    let mut totals: HashMap<(String, String), u32> = HashMap::new();
+    for entry in &entries {
+        *totals
+            .entry((entry.directory.clone(), entry.command.clone()))
+            .or_insert(0) += entry.count;
+    }
+
+    // Walk newest first so the first occurrence seen is the one kept
+    let mut kept: Vec<HistoryEntry> = entries
+        .into_iter()
+        .rev()
+        .filter_map(|mut entry| {
+            let count = totals.remove(&(entry.directory.clone(), entry.command.clone()))?;
+            entry.count = count;
+            Some(entry)
+        })
+        .collect();
+
+    kept.reverse();
+    kept
+}
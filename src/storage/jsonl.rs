@@ -0,0 +1,212 @@
+use anyhow::Result;
+use fs4::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use super::{EntryFilter, HistoryStore, RewriteFn};
+use crate::history::HistoryEntry;
+
+/// JSON Lines backend: one serialized `HistoryEntry` per line.
+pub struct JsonlStore {
+    path: PathBuf,
+    /// Read without taking locks, for files we don't own
+    read_only: bool,
+}
+
+impl JsonlStore {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        // Ensure parent directory exists for custom paths
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        Ok(Self { path, read_only: false })
+    }
+
+    /// Open a file only to read from it, creating nothing.
+    pub fn read_only(path: PathBuf) -> Self {
+        Self { path, read_only: true }
+    }
+
+    /// Take an advisory lock on the sidecar `<file>.lock`, held until the
+    /// returned handle is dropped.
+    ///
+    /// AIDEV-NOTE: the lock lives in a separate file rather than on the history
+    /// file itself so it survives the history file being replaced or removed.
+    fn lock(&self, exclusive: bool) -> Result<File> {
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?;
+
+        // AIDEV-NOTE: called through the trait so they don't resolve to the
+        // inherent File::lock methods, which need a newer rustc than our MSRV
+        if exclusive {
+            FileExt::lock(&lock_file)?;
+        } else {
+            FileExt::lock_shared(&lock_file)?;
+        }
+        Ok(lock_file)
+    }
+
+    fn read_all(&self) -> Result<Vec<HistoryEntry>> {
+        let mut entries = Vec::new();
+
+        if !self.path.exists() {
+            return Ok(entries); // Missing files are simply empty
+        }
+
+        let reader = BufReader::new(File::open(&self.path)?);
+
+        for line in reader.lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => continue, // Skip read errors
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // AIDEV-NOTE: skip malformed lines instead of failing
+            if let Ok(entry) = serde_json::from_str::<HistoryEntry>(&line) {
+                entries.push(entry);
+            }
+        }
+
+        entries.sort_by_key(|entry| entry.timestamp);
+
+        Ok(entries)
+    }
+
+    /// Atomically replace the history file with `entries`.
+    ///
+    /// AIDEV-NOTE: entries go to a temp file in the same directory which is
+    /// fsynced and renamed over the original, so a crash or Ctrl+C midway
+    /// leaves either the old or the new history, never a partial one. Callers
+    /// must hold the exclusive lock.
+    fn replace_all(&self, entries: &[HistoryEntry]) -> Result<()> {
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "history.jsonl".to_string());
+        let temp_path = dir.join(format!(".{}.tmp-{}", file_name, std::process::id()));
+
+        let result = (|| -> Result<()> {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            for entry in entries {
+                serde_json::to_writer(&mut writer, entry)?;
+                writer.write_all(b"\n")?;
+            }
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            // AIDEV-NOTE: the temp file gets umask permissions; keep a private
+            // (0600) history private after the rename
+            if let Ok(metadata) = std::fs::metadata(&self.path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.sync_all()?;
+
+            std::fs::rename(&temp_path, &self.path)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+            return result;
+        }
+
+        // Persist the rename itself; not all platforms allow syncing a directory
+        if let Ok(dir_handle) = File::open(&dir) {
+            let _ = dir_handle.sync_all();
+        }
+
+        Ok(())
+    }
+}
+
+impl HistoryStore for JsonlStore {
+    fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        // AIDEV-NOTE: build the whole line first and hand it to a single
+        // write() so that a line longer than PIPE_BUF is never interleaved
+        // with another shell's append; the lock covers writers on filesystems
+        // where O_APPEND alone does not guarantee that
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let _lock = self.lock(true)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+
+        Ok(())
+    }
+
+    fn append_batch(&self, entries: &[HistoryEntry]) -> Result<()> {
+        let mut buffer = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut buffer, entry)?;
+            buffer.push(b'\n');
+        }
+
+        let _lock = self.lock(true)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&buffer)?;
+
+        Ok(())
+    }
+
+    fn scan(&self, filter: &EntryFilter) -> Result<Vec<HistoryEntry>> {
+        // AIDEV-NOTE: read sources may be read-only or synced from elsewhere,
+        // so they are read unlocked rather than given a lock file of our own
+        let _lock = if self.read_only { None } else { Some(self.lock(false)?) };
+        let mut entries = self.read_all()?;
+        entries.retain(|entry| filter.matches(entry));
+        Ok(entries)
+    }
+
+    fn delete(&self, filter: &EntryFilter) -> Result<usize> {
+        // AIDEV-NOTE: hold the lock across read and rewrite so concurrent
+        // appends can't land in between and be lost
+        let _lock = self.lock(true)?;
+        let entries = self.read_all()?;
+
+        if filter.is_all() {
+            if self.path.exists() {
+                std::fs::remove_file(&self.path)?;
+            }
+            return Ok(entries.len());
+        }
+
+        // AIDEV-NOTE: rewrite file excluding matching entries
+        let (removed, kept): (Vec<_>, Vec<_>) =
+            entries.into_iter().partition(|entry| filter.matches(entry));
+        if !removed.is_empty() {
+            self.replace_all(&kept)?;
+        }
+
+        Ok(removed.len())
+    }
+
+    fn rewrite(&self, transform: &mut RewriteFn) -> Result<()> {
+        let _lock = self.lock(true)?;
+        let entries = transform(self.read_all()?)?;
+        self.replace_all(&entries)
+    }
+}
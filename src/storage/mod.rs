@@ -0,0 +1,507 @@
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+
+use crate::history::HistoryEntry;
+
+mod compact;
+mod jsonl;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use compact::{CompactPolicy, CompactReport};
+pub use jsonl::JsonlStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Structured description of which entries a query is interested in.
+///
+/// AIDEV-NOTE: backends receive this instead of a closure so that indexed
+/// stores can translate scope queries into lookups rather than full scans.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    /// Match this directory and everything below it
+    pub directory: Option<String>,
+    pub session_id: Option<String>,
+    pub hostname: Option<String>,
+    /// Exact command text
+    pub command: Option<String>,
+    /// Exact start time, to single out one entry
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl EntryFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn directory(directory: &str) -> Self {
+        Self {
+            directory: Some(directory.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn session(session_id: &str) -> Self {
+        Self {
+            session_id: Some(session_id.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn hostname(hostname: &str) -> Self {
+        Self {
+            hostname: Some(hostname.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Every run of `command`.
+    pub fn command(command: &str) -> Self {
+        Self {
+            command: Some(command.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Just `entry`, identified by its start time, session and command.
+    pub fn entry(entry: &HistoryEntry) -> Self {
+        Self {
+            session_id: Some(entry.session_id.clone()),
+            command: Some(entry.command.clone()),
+            timestamp: Some(entry.timestamp),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.directory.is_none()
+            && self.session_id.is_none()
+            && self.hostname.is_none()
+            && self.command.is_none()
+            && self.timestamp.is_none()
+    }
+
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        if let Some(directory) = &self.directory {
+            // AIDEV-NOTE: local entries include current dir and subdirectories
+            if !is_within(&entry.directory, directory) {
+                return false;
+            }
+        }
+        if let Some(session_id) = &self.session_id {
+            if entry.session_id != *session_id {
+                return false;
+            }
+        }
+        if let Some(hostname) = &self.hostname {
+            if entry.hostname.as_ref() != Some(hostname) {
+                return false;
+            }
+        }
+        if let Some(command) = &self.command {
+            if entry.command != *command {
+                return false;
+            }
+        }
+        if let Some(timestamp) = &self.timestamp {
+            if entry.timestamp != *timestamp {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Whether `path` is `directory` or somewhere below it.
+pub fn is_within(path: &str, directory: &str) -> bool {
+    // AIDEV-NOTE: trimmed so that `/` (a project root, or `dir:/`) doesn't
+    // turn into a `//` prefix that no path starts with
+    path == directory
+        || path
+            .strip_prefix(directory.trim_end_matches('/'))
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[derive(Debug, Default)]
+pub struct StoreStats {
+    pub total_entries: usize,
+    pub unique_commands: usize,
+}
+
+impl StoreStats {
+    pub fn from_entries(entries: &[HistoryEntry]) -> Self {
+        let unique: HashSet<_> = entries.iter().map(|e| &e.command).collect();
+        Self {
+            total_entries: entries.len(),
+            unique_commands: unique.len(),
+        }
+    }
+}
+
+pub type RewriteFn<'a> = dyn FnMut(Vec<HistoryEntry>) -> Result<Vec<HistoryEntry>> + 'a;
+
+/// A place history entries are persisted to and read back from.
+pub trait HistoryStore {
+    /// Append a single entry.
+    fn append(&self, entry: &HistoryEntry) -> Result<()>;
+
+    /// Append many entries at once; backends may override this to batch writes.
+    fn append_batch(&self, entries: &[HistoryEntry]) -> Result<()> {
+        for entry in entries {
+            self.append(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Return all entries matching `filter`, oldest first.
+    fn scan(&self, filter: &EntryFilter) -> Result<Vec<HistoryEntry>>;
+
+    /// Remove all entries matching `filter`, returning how many were removed.
+    fn delete(&self, filter: &EntryFilter) -> Result<usize>;
+
+    /// Atomically replace the contents of the store with `transform` applied
+    /// to its current entries (oldest first). Concurrent appends are blocked
+    /// until the rewrite completes, so none are lost.
+    fn rewrite(&self, transform: &mut RewriteFn) -> Result<()>;
+
+    fn stats(&self) -> Result<StoreStats> {
+        Ok(StoreStats::from_entries(&self.scan(&EntryFilter::all())?))
+    }
+}
+
+/// Open a store from a `--database` location.
+///
+/// Accepts either a plain path or a URL of the form `scheme://path`, e.g.
+/// `jsonl:///home/me/history.jsonl` or `sqlite:///home/me/history.db`.
+/// Plain paths ending in `.db`, `.sqlite` or `.sqlite3` use SQLite, anything
+/// else JSON Lines.
+pub fn open_store(location: &str) -> Result<Box<dyn HistoryStore>> {
+    match parse_location(location) {
+        ("jsonl" | "file", path) => Ok(Box::new(JsonlStore::new(PathBuf::from(path))?)),
+        #[cfg(feature = "sqlite")]
+        ("sqlite", path) => Ok(Box::new(SqliteStore::new(PathBuf::from(path))?)),
+        (scheme, _) => unsupported(scheme, location),
+    }
+}
+
+/// Open a store given as a read source, which is only ever read from.
+///
+/// AIDEV-NOTE: read sources are often other hosts' synced files or
+/// read-only mounts, so opening one must not create directories, files or
+/// schemas; a missing JSONL file reads as empty, a missing database fails.
+pub fn open_read_source(location: &str) -> Result<Box<dyn HistoryStore>> {
+    match parse_location(location) {
+        ("jsonl" | "file", path) => Ok(Box::new(JsonlStore::read_only(PathBuf::from(path)))),
+        #[cfg(feature = "sqlite")]
+        ("sqlite", path) => Ok(Box::new(SqliteStore::read_only(PathBuf::from(path))?)),
+        (scheme, _) => unsupported(scheme, location),
+    }
+}
+
+/// Split a location into its scheme and path.
+fn parse_location(location: &str) -> (&str, &str) {
+    match location.split_once("://") {
+        Some((scheme, path)) => (scheme, path),
+        None if is_sqlite_path(location) => ("sqlite", location),
+        None => ("jsonl", location),
+    }
+}
+
+fn unsupported<T>(scheme: &str, location: &str) -> Result<T> {
+    match scheme {
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => bail!("rehash was built without SQLite support (enable the `sqlite` feature)"),
+        other => bail!("Unsupported storage scheme '{}' in '{}'", other, location),
+    }
+}
+
+fn is_sqlite_path(location: &str) -> bool {
+    matches!(
+        std::path::Path::new(location).extension().and_then(|ext| ext.to_str()),
+        Some("db" | "sqlite" | "sqlite3")
+    )
+}
+
+pub fn default_location() -> Result<String> {
+    let mut default_path = dirs::data_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not find data directory"))?;
+
+    default_path.push("rehash");
+    std::fs::create_dir_all(&default_path)?;
+    default_path.push("history.jsonl");
+    Ok(default_path.to_string_lossy().to_string())
+}
+
+pub struct Storage {
+    primary: Box<dyn HistoryStore>,
+    read_sources: Vec<Box<dyn HistoryStore>>,
+    /// Where `start` parks entries until `end`: `<primary>.pending`
+    pending_dir: PathBuf,
+}
+
+impl Storage {
+    pub fn new(custom_path: Option<String>, additional_read_sources: Vec<String>) -> Result<Self> {
+        let location = match custom_path {
+            Some(path) => path,
+            None => default_location()?,
+        };
+
+        let primary = open_store(&location)?;
+        // AIDEV-NOTE: skip read sources that can't be opened, as reading does
+        let read_sources = additional_read_sources
+            .iter()
+            .filter_map(|source| open_read_source(source).ok())
+            .collect();
+
+        let mut pending_dir = PathBuf::from(parse_location(&location).1).into_os_string();
+        pending_dir.push(".pending");
+
+        Ok(Self {
+            primary,
+            read_sources,
+            pending_dir: PathBuf::from(pending_dir),
+        })
+    }
+
+    /// Directory for commands that have started but not yet ended.
+    ///
+    /// AIDEV-NOTE: it sits next to the primary store so that shells using
+    /// different `--database`s don't share in-flight commands.
+    pub fn pending_dir(&self) -> &std::path::Path {
+        &self.pending_dir
+    }
+
+    pub fn add_entry(&self, entry: HistoryEntry) -> Result<()> {
+        self.primary.append(&entry)
+    }
+
+    pub fn get_all_entries(&self) -> Result<Vec<HistoryEntry>> {
+        self.read_entries(&EntryFilter::all())
+    }
+
+    pub fn get_entries(&self, filter: &EntryFilter) -> Result<Vec<HistoryEntry>> {
+        self.read_entries(filter)
+    }
+
+    fn read_entries(&self, filter: &EntryFilter) -> Result<Vec<HistoryEntry>> {
+        let mut all_entries = self.primary.scan(filter)?;
+
+        // Read from all sources
+        for source in &self.read_sources {
+            match source.scan(filter) {
+                Ok(entries) => all_entries.extend(entries),
+                // AIDEV-NOTE: skip read-only sources that can't be read
+                Err(_) => continue,
+            }
+        }
+
+        // Sort by timestamp to maintain chronological order
+        all_entries.sort_by_key(|entry| entry.timestamp);
+
+        Ok(all_entries)
+    }
+
+    pub fn stats(&self) -> Result<StoreStats> {
+        if self.read_sources.is_empty() {
+            return self.primary.stats();
+        }
+        Ok(StoreStats::from_entries(&self.get_all_entries()?))
+    }
+
+    /// Copy entries into the primary store, skipping any already present.
+    ///
+    /// Returns the number of entries actually added.
+    pub fn import_entries(&self, entries: Vec<HistoryEntry>) -> Result<usize> {
+        // AIDEV-NOTE: (timestamp, session, command) identifies an entry across
+        // stores, which makes repeated migrations and imports idempotent
+        let mut seen: HashSet<_> = self
+            .primary
+            .scan(&EntryFilter::all())?
+            .into_iter()
+            .map(|e| (e.timestamp, e.session_id, e.command))
+            .collect();
+
+        let new_entries: Vec<_> = entries
+            .into_iter()
+            .filter(|e| seen.insert((e.timestamp, e.session_id.clone(), e.command.clone())))
+            .collect();
+
+        self.primary.append_batch(&new_entries)?;
+        Ok(new_entries.len())
+    }
+
+    /// Entries matching `filter` in the primary store alone: the ones
+    /// `delete_entries` would remove.
+    pub fn get_deletable_entries(&self, filter: &EntryFilter) -> Result<Vec<HistoryEntry>> {
+        self.primary.scan(filter)
+    }
+
+    /// Copy entries whose timestamps were made up into the primary store,
+    /// skipping commands their session already has.
+    ///
+    /// AIDEV-NOTE: a made-up timestamp shifts whenever the file it came from
+    /// changes, so only (session, command) identifies these across imports
+    pub fn import_untimed(&self, entries: Vec<HistoryEntry>) -> Result<usize> {
+        let mut seen: HashSet<_> = self
+            .primary
+            .scan(&EntryFilter::all())?
+            .into_iter()
+            .map(|e| (e.session_id, e.command))
+            .collect();
+
+        let new_entries: Vec<_> = entries
+            .into_iter()
+            .filter(|e| seen.insert((e.session_id.clone(), e.command.clone())))
+            .collect();
+
+        self.primary.append_batch(&new_entries)?;
+        Ok(new_entries.len())
+    }
+
+    /// Remove entries matching `filter` from the primary store.
+    pub fn clear_history(&self, filter: &EntryFilter) -> Result<()> {
+        self.delete_entries(filter)?;
+        Ok(())
+    }
+
+    /// Remove entries matching `filter` from the primary store, returning how
+    /// many were removed. Entries in read sources are left alone.
+    pub fn delete_entries(&self, filter: &EntryFilter) -> Result<usize> {
+        self.primary.delete(filter)
+    }
+
+    /// Compact the primary store according to `policy`.
+    ///
+    /// With `dry_run` nothing is written; the report describes what would go.
+    pub fn compact(&self, policy: &CompactPolicy, dry_run: bool) -> Result<CompactReport> {
+        let now = Utc::now();
+
+        if dry_run {
+            let entries = self.primary.scan(&EntryFilter::all())?;
+            return Ok(compact::compact_entries(entries, policy, now).1);
+        }
+
+        let mut report = CompactReport::default();
+        self.primary.rewrite(&mut |entries| {
+            let (kept, result) = compact::compact_entries(entries, policy, now);
+            report = result;
+            Ok(kept)
+        })?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, session_id: &str, seconds: i64) -> HistoryEntry {
+        HistoryEntry {
+            session_id: session_id.to_string(),
+            ..HistoryEntry::test(command, "/src", 0, DateTime::from_timestamp(seconds, 0).unwrap())
+        }
+    }
+
+    #[test]
+    fn entry_and_command_filters() {
+        let make = entry("make", "s1", 10);
+
+        assert!(EntryFilter::entry(&make).matches(&make));
+        assert!(!EntryFilter::entry(&make).matches(&entry("make", "s1", 11)));
+        assert!(!EntryFilter::entry(&make).matches(&entry("make", "s2", 10)));
+        assert!(!EntryFilter::entry(&make).matches(&entry("make test", "s1", 10)));
+
+        assert!(EntryFilter::command("make").matches(&entry("make", "s2", 99)));
+        assert!(!EntryFilter::command("make").matches(&entry("make test", "s1", 10)));
+    }
+
+    #[test]
+    fn directory_filters_include_subdirectories() {
+        let mut entry = entry("make", "s1", 10);
+        for (directory, filter, matches) in [
+            ("/src/app", "/src/app", true),
+            ("/src/app/lib", "/src/app", true),
+            ("/src/application", "/src/app", false),
+            ("/src", "/src/app", false),
+            ("/src/app", "/", true),
+            ("/", "/", true),
+            ("/src/app/lib", "/src/app/", true),
+        ] {
+            entry.directory = directory.to_string();
+            assert_eq!(EntryFilter::directory(filter).matches(&entry), matches, "{} in {}", directory, filter);
+        }
+    }
+
+    /// Deleting touches only the primary store, whatever its backend.
+    fn check_delete(primary: String, dir: &std::path::Path) {
+        let source = dir.join("other.jsonl");
+        let lines: Vec<_> = [entry("make", "s9", 5), entry("ls", "s9", 6)]
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+            .collect();
+        std::fs::write(&source, lines.concat()).unwrap();
+
+        let storage = Storage::new(Some(primary), vec![source.to_string_lossy().to_string()]).unwrap();
+        let runs = [entry("make", "s1", 10), entry("make", "s1", 20), entry("ls", "s1", 30)];
+        assert_eq!(storage.import_entries(runs.to_vec()).unwrap(), 3);
+
+        assert_eq!(storage.get_entries(&EntryFilter::command("make")).unwrap().len(), 3);
+        assert_eq!(storage.get_deletable_entries(&EntryFilter::command("make")).unwrap().len(), 2);
+
+        assert_eq!(storage.delete_entries(&EntryFilter::entry(&runs[0])).unwrap(), 1);
+        assert_eq!(storage.delete_entries(&EntryFilter::entry(&runs[0])).unwrap(), 0);
+        assert_eq!(storage.delete_entries(&EntryFilter::command("make")).unwrap(), 1);
+
+        let left: Vec<_> = storage
+            .get_all_entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.command, entry.session_id))
+            .collect();
+        let expected = [("make", "s9"), ("ls", "s9"), ("ls", "s1")];
+        assert_eq!(left, expected.map(|(c, s)| (c.to_string(), s.to_string())));
+        // Read sources are read without a lock file of ours next to them
+        assert!(!dir.join("other.jsonl.lock").exists());
+    }
+
+    #[test]
+    fn delete_jsonl_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let primary = dir.path().join("history.jsonl");
+        check_delete(primary.to_string_lossy().to_string(), dir.path());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn delete_sqlite_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let primary = dir.path().join("history.db");
+        check_delete(format!("sqlite://{}", primary.display()), dir.path());
+    }
+
+    #[test]
+    fn missing_read_sources_are_skipped_not_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let primary = dir.path().join("history.jsonl");
+        let missing = dir.path().join("nfs/history.jsonl");
+        let sources = vec![
+            missing.to_string_lossy().to_string(),
+            format!("sqlite://{}", dir.path().join("other.db").display()),
+            format!("ftp://{}", dir.path().join("remote").display()),
+        ];
+
+        let storage = Storage::new(Some(primary.to_string_lossy().to_string()), sources).unwrap();
+        storage.add_entry(entry("make", "s1", 10)).unwrap();
+        assert_eq!(storage.get_all_entries().unwrap().len(), 1);
+
+        let mut created: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|file| file.unwrap().file_name())
+            .collect();
+        created.retain(|name| !name.to_string_lossy().starts_with("history.jsonl"));
+        assert!(created.is_empty(), "{:?}", created);
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Row, Transaction, TransactionBehavior};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{EntryFilter, HistoryStore, RewriteFn, StoreStats};
+use crate::history::HistoryEntry;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        command TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        directory TEXT NOT NULL,
+        exit_code INTEGER NOT NULL,
+        session_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_history_directory ON history(directory);
+    CREATE INDEX IF NOT EXISTS idx_history_session_id ON history(session_id);
+    CREATE INDEX IF NOT EXISTS idx_history_timestamp ON history(timestamp);
+    CREATE INDEX IF NOT EXISTS idx_history_exit_code ON history(exit_code);
+";
+
+/// Schema changes applied in order on top of `SCHEMA`; `PRAGMA user_version`
+/// records how many have been run against a database.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE history ADD COLUMN count INTEGER NOT NULL DEFAULT 1",
+    "ALTER TABLE history ADD COLUMN end_timestamp INTEGER;
+     ALTER TABLE history ADD COLUMN duration_ms INTEGER;",
+    "ALTER TABLE history ADD COLUMN hostname TEXT;
+     ALTER TABLE history ADD COLUMN user TEXT;
+     ALTER TABLE history ADD COLUMN shell TEXT;
+     CREATE INDEX IF NOT EXISTS idx_history_hostname ON history(hostname);",
+    "ALTER TABLE history ADD COLUMN project_root TEXT;
+     ALTER TABLE history ADD COLUMN branch TEXT;",
+];
+
+const COLUMNS: &str = "command, timestamp, directory, exit_code, session_id, count, \
+                       end_timestamp, duration_ms, hostname, user, shell, project_root, branch";
+
+/// Embedded SQLite backend with indexes on the columns scopes filter by.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let conn = Connection::open(&path)?;
+        // AIDEV-NOTE: many shells finishing at once contend for the write lock;
+        // wait for it instead of failing the append
+        conn.busy_timeout(Duration::from_secs(5))?;
+        // AIDEV-NOTE: WAL lets shells append while an interactive search is reading
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Self::migrate(&conn)?;
+
+        Ok(Self { conn })
+    }
+
+    /// Open an existing database only to read from it, leaving its schema
+    /// as it is.
+    pub fn read_only(path: PathBuf) -> Result<Self> {
+        let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < MIGRATIONS.len() {
+            bail!(
+                "{} has schema version {} rather than {}; open it with --database to upgrade it",
+                path.display(),
+                version,
+                MIGRATIONS.len()
+            );
+        }
+
+        Ok(Self { conn })
+    }
+
+    fn migrate(conn: &Connection) -> Result<()> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= MIGRATIONS.len() {
+            return Ok(());
+        }
+
+        // AIDEV-NOTE: re-read the version under the write lock; another shell
+        // may have migrated the database while we were waiting for it
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+        let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for migration in MIGRATIONS.iter().skip(version) {
+            tx.execute_batch(migration)?;
+        }
+        tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn insert(conn: &Connection, entry: &HistoryEntry) -> Result<()> {
+        conn.execute(
+            &format!("INSERT INTO history ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)", COLUMNS),
+            params![
+                entry.command,
+                timestamp_to_nanos(entry.timestamp)?,
+                entry.directory,
+                entry.exit_code,
+                entry.session_id,
+                entry.count,
+                entry.end_timestamp.map(timestamp_to_nanos).transpose()?,
+                entry.duration_ms.map(|ms| ms.min(i64::MAX as u64) as i64),
+                entry.hostname,
+                entry.user,
+                entry.shell,
+                entry.project_root,
+                entry.branch,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn entry_from_row(row: &Row) -> rusqlite::Result<HistoryEntry> {
+        Ok(HistoryEntry {
+            command: row.get(0)?,
+            timestamp: DateTime::from_timestamp_nanos(row.get(1)?),
+            directory: row.get(2)?,
+            exit_code: row.get(3)?,
+            session_id: row.get(4)?,
+            count: row.get(5)?,
+            end_timestamp: row.get::<_, Option<i64>>(6)?.map(DateTime::from_timestamp_nanos),
+            duration_ms: row.get::<_, Option<i64>>(7)?.map(|ms| ms.max(0) as u64),
+            hostname: row.get(8)?,
+            user: row.get(9)?,
+            shell: row.get(10)?,
+            project_root: row.get(11)?,
+            branch: row.get(12)?,
+        })
+    }
+}
+
+/// Timestamps are stored as nanoseconds since the epoch, which covers the
+/// years 1677 to 2262.
+fn timestamp_to_nanos(timestamp: DateTime<Utc>) -> Result<i64> {
+    timestamp
+        .timestamp_nanos_opt()
+        .ok_or_else(|| anyhow!("Timestamp {} is outside the range SQLite history can store", timestamp))
+}
+
+/// Translate a filter into a WHERE clause that can be answered from indexes.
+fn where_clause(filter: &EntryFilter) -> Result<(String, Vec<Value>)> {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+
+    if let Some(directory) = &filter.directory {
+        // AIDEV-NOTE: '0' sorts directly after '/', so this range selects every
+        // subdirectory while still using idx_history_directory (LIKE would not)
+        let n = values.len();
+        conditions.push(format!(
+            "(directory = ?{a} OR (directory >= ?{b} AND directory < ?{c}))",
+            a = n + 1,
+            b = n + 2,
+            c = n + 3
+        ));
+        let parent = directory.trim_end_matches('/');
+        values.push(Value::Text(directory.clone()));
+        values.push(Value::Text(format!("{}/", parent)));
+        values.push(Value::Text(format!("{}0", parent)));
+    }
+    if let Some(session_id) = &filter.session_id {
+        conditions.push(format!("session_id = ?{}", values.len() + 1));
+        values.push(Value::Text(session_id.clone()));
+    }
+    if let Some(hostname) = &filter.hostname {
+        conditions.push(format!("hostname = ?{}", values.len() + 1));
+        values.push(Value::Text(hostname.clone()));
+    }
+    if let Some(command) = &filter.command {
+        conditions.push(format!("command = ?{}", values.len() + 1));
+        values.push(Value::Text(command.clone()));
+    }
+    if let Some(timestamp) = &filter.timestamp {
+        conditions.push(format!("timestamp = ?{}", values.len() + 1));
+        values.push(Value::Integer(timestamp_to_nanos(*timestamp)?));
+    }
+
+    if conditions.is_empty() {
+        Ok((String::new(), values))
+    } else {
+        Ok((format!(" WHERE {}", conditions.join(" AND ")), values))
+    }
+}
+
+impl HistoryStore for SqliteStore {
+    fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        Self::insert(&self.conn, entry)
+    }
+
+    fn append_batch(&self, entries: &[HistoryEntry]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for entry in entries {
+            Self::insert(&tx, entry)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn scan(&self, filter: &EntryFilter) -> Result<Vec<HistoryEntry>> {
+        let (clause, values) = where_clause(filter)?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM history{} ORDER BY timestamp, id",
+            COLUMNS, clause
+        ))?;
+
+        let entries = stmt
+            .query_map(params_from_iter(values), Self::entry_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    fn delete(&self, filter: &EntryFilter) -> Result<usize> {
+        let (clause, values) = where_clause(filter)?;
+        let removed = self
+            .conn
+            .execute(&format!("DELETE FROM history{}", clause), params_from_iter(values))?;
+        Ok(removed)
+    }
+
+    fn rewrite(&self, transform: &mut RewriteFn) -> Result<()> {
+        // AIDEV-NOTE: IMMEDIATE takes the write lock up front so appends from
+        // other shells wait for the rewrite instead of slipping in between
+        // the read and the delete
+        let tx = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?;
+        let entries = transform(self.scan(&EntryFilter::all())?)?;
+        tx.execute("DELETE FROM history", [])?;
+        for entry in &entries {
+            Self::insert(&tx, entry)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<StoreStats> {
+        let (total, unique): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT command) FROM history",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok(StoreStats {
+            total_entries: total as usize,
+            unique_commands: unique as usize,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, seconds: i64) -> HistoryEntry {
+        HistoryEntry::test(command, "/src/app", 0, DateTime::from_timestamp(seconds, 123_456_789).unwrap())
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::new(dir.path().join("history.db")).unwrap();
+
+        let mut full = entry("cargo test", 1_700_000_000);
+        full.exit_code = 101;
+        full.hostname = Some("box".to_string());
+        full.user = Some("me".to_string());
+        full.shell = Some("zsh".to_string());
+        full.project_root = Some("/src/app".to_string());
+        full.branch = Some("main".to_string());
+        full.end_timestamp = Some(full.timestamp + chrono::Duration::milliseconds(1500));
+        full.duration_ms = Some(1500);
+        full.count = 3;
+        let bare = entry("ls", 1_600_000_000);
+
+        store.append_batch(&[full.clone(), bare.clone()]).unwrap();
+        let stored = store.scan(&EntryFilter::all()).unwrap();
+        assert_eq!(serde_json::to_value(&stored).unwrap(), serde_json::to_value([bare, full]).unwrap());
+
+        assert_eq!(store.scan(&EntryFilter::directory("/src")).unwrap().len(), 2);
+        assert_eq!(store.scan(&EntryFilter::directory("/")).unwrap().len(), 2);
+        assert_eq!(store.scan(&EntryFilter::directory("/src/a")).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn timestamps_sqlite_cannot_hold_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::new(dir.path().join("history.db")).unwrap();
+
+        let late = entry("ls", 10_000_000_000);
+        assert!(store.append(&late).is_err());
+        assert!(store.scan(&EntryFilter::entry(&late)).is_err());
+        assert!(store.scan(&EntryFilter::all()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn old_databases_migrate_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute(
+            "INSERT INTO history (command, timestamp, directory, exit_code, session_id)
+             VALUES ('make', 1700000000000000000, '/src', 0, 's1')",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        // Not upgraded behind the back of a read-only opener
+        assert!(SqliteStore::read_only(path.clone()).is_err());
+
+        for _ in 0..2 {
+            let store = SqliteStore::new(path.clone()).unwrap();
+            let version: usize = store.conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+            assert_eq!(version, MIGRATIONS.len());
+
+            let entries = store.scan(&EntryFilter::all()).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].command, "make");
+            assert_eq!(entries[0].count, 1);
+            assert_eq!(entries[0].duration_ms, None);
+        }
+        assert_eq!(SqliteStore::read_only(path).unwrap().stats().unwrap().total_entries, 1);
+    }
+}
@@ -0,0 +1,398 @@
+use anyhow::{bail, Context, Result};
+use console::Style;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::history::SearchScope;
+
+/// Colors of the interactive search: one of the built-in themes or a
+/// `[themes.<name>]` table from the config.
+///
+/// Each value is a dotted style such as `yellow.bold` or `black.on_white`:
+/// a color (`black`, `red`, `green`, `yellow`, `blue`, `magenta`, `cyan`,
+/// `white` or a 256-color number), `bright`, a background (`on_<color>`,
+/// `on_bright`) and attributes (`bold`, `dim`, `italic`, `underlined`,
+/// `reverse`, `strikethrough`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub scope_global: String,
+    pub scope_session: String,
+    pub scope_local: String,
+    pub scope_host: String,
+    pub scope_project: String,
+    /// Match mode and the program name in the header
+    pub header: String,
+    pub help: String,
+    /// Unmatched command text
+    pub text: String,
+    /// Characters the query matched
+    pub matched: String,
+    /// Background of the selected row
+    pub selection: String,
+    pub selected_text: String,
+    pub selected_match: String,
+    /// Marker of entries marked for a batch action
+    pub mark: String,
+    pub time: String,
+    pub duration: String,
+    pub runs: String,
+    /// Non-zero exit codes
+    pub error: String,
+    pub prompt: String,
+    pub query: String,
+    pub placeholder: String,
+    /// Confirmation messages on the prompt line
+    pub message: String,
+    /// Questions asked before deleting
+    pub warning: String,
+    /// Preview pane separator
+    pub border: String,
+    /// Field names in the preview pane
+    pub label: String,
+    /// The full command in the preview pane
+    pub preview: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// Names of the built-in themes.
+    pub const BUILT_IN: [&'static str; 3] = ["dark", "light", "no-color"];
+
+    /// Light text on a dark background.
+    pub fn dark() -> Self {
+        Self {
+            scope_global: "cyan.bold".into(),
+            scope_session: "yellow.bold".into(),
+            scope_local: "green.bold".into(),
+            scope_host: "magenta.bold".into(),
+            scope_project: "blue.bold".into(),
+            header: "white".into(),
+            help: "black.bright".into(),
+            text: "white".into(),
+            matched: "yellow.bold".into(),
+            selection: "on_white".into(),
+            selected_text: "black.bold".into(),
+            selected_match: "blue.bold.underlined".into(),
+            mark: "green.bold".into(),
+            time: "blue".into(),
+            duration: "magenta".into(),
+            runs: "cyan".into(),
+            error: "red".into(),
+            prompt: "cyan.bold".into(),
+            query: "white.bold".into(),
+            placeholder: "blue.italic".into(),
+            message: "green".into(),
+            warning: "red.bold".into(),
+            border: "black.bright".into(),
+            label: "blue".into(),
+            preview: "white.bold".into(),
+        }
+    }
+
+    /// Dark text on a light background; avoids yellow and white text.
+    pub fn light() -> Self {
+        Self {
+            scope_global: "blue.bold".into(),
+            scope_session: "magenta.bold".into(),
+            scope_local: "green.bold".into(),
+            scope_host: "red.bold".into(),
+            scope_project: "cyan.bold".into(),
+            header: "black".into(),
+            help: "black.bright".into(),
+            text: "black".into(),
+            matched: "blue.bold.underlined".into(),
+            selection: "on_blue".into(),
+            selected_text: "white.bold".into(),
+            selected_match: "yellow.bold.underlined".into(),
+            mark: "green.bold".into(),
+            time: "blue".into(),
+            duration: "magenta".into(),
+            runs: "green".into(),
+            error: "red".into(),
+            prompt: "blue.bold".into(),
+            query: "black.bold".into(),
+            placeholder: "black.bright.italic".into(),
+            message: "green".into(),
+            warning: "red.bold".into(),
+            border: "black.bright".into(),
+            label: "blue".into(),
+            preview: "black.bold".into(),
+        }
+    }
+
+    /// No colors at all, only attributes, for `NO_COLOR`.
+    pub fn no_color() -> Self {
+        Self {
+            scope_global: "bold".into(),
+            scope_session: "bold".into(),
+            scope_local: "bold".into(),
+            scope_host: "bold".into(),
+            scope_project: "bold".into(),
+            header: String::new(),
+            help: "dim".into(),
+            text: String::new(),
+            matched: "bold.underlined".into(),
+            selection: "reverse".into(),
+            selected_text: String::new(),
+            selected_match: "bold.underlined".into(),
+            mark: "bold".into(),
+            time: String::new(),
+            duration: String::new(),
+            runs: String::new(),
+            error: "bold".into(),
+            prompt: "bold".into(),
+            query: "bold".into(),
+            placeholder: "dim".into(),
+            message: "bold".into(),
+            warning: "bold".into(),
+            border: "dim".into(),
+            label: "bold".into(),
+            preview: "bold".into(),
+        }
+    }
+
+    pub fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "no-color" => Some(Self::no_color()),
+            _ => None,
+        }
+    }
+
+    /// Find theme `name` among the `custom` themes from the config, then the
+    /// built-in ones. A custom theme changes the styles it lists on top of
+    /// its `base` theme, `dark` unless given.
+    pub fn resolve(name: &str, custom: &BTreeMap<String, BTreeMap<String, String>>) -> Result<Self> {
+        let mut chain = Vec::new();
+        let mut current = name;
+        let mut theme = loop {
+            // A custom theme named like a built-in one builds on the latter
+            let seen = chain.iter().any(|(seen, _)| *seen == current);
+            match custom.get(current) {
+                Some(definition) if !seen => {
+                    chain.push((current, definition));
+                    current = definition.get("base").map_or("dark", String::as_str);
+                }
+                _ => match Self::built_in(current) {
+                    Some(theme) => break theme,
+                    None if seen => bail!("theme '{}' is its own base", current),
+                    None => bail!(
+                        "unknown theme '{}' (expected {} or a [themes.{}] table)",
+                        current,
+                        Self::BUILT_IN.join(", "),
+                        current
+                    ),
+                },
+            }
+        };
+
+        for (theme_name, definition) in chain.into_iter().rev() {
+            let overrides = definition
+                .iter()
+                .filter(|(slot, _)| *slot != "base")
+                .map(|(slot, spec)| (slot.clone(), spec.clone()))
+                .collect();
+            theme = theme
+                .with_overrides(&overrides)
+                .with_context(|| format!("in theme '{}'", theme_name))?;
+        }
+        Ok(theme)
+    }
+
+    /// Replace the styles named in `overrides`, e.g. `matched = "red.bold"`.
+    pub fn with_overrides(&self, overrides: &BTreeMap<String, String>) -> Result<Self> {
+        let mut table = match toml::Value::try_from(self)? {
+            toml::Value::Table(table) => table,
+            _ => unreachable!("a theme serializes to a table"),
+        };
+        for (slot, spec) in overrides {
+            table.insert(slot.clone(), toml::Value::String(spec.clone()));
+        }
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+
+    /// Parse every style, failing on the first invalid one.
+    pub fn styles(&self) -> Result<Styles> {
+        let selected = |spec: &str| parse_style(&format!("{}.{}", spec, self.selection));
+
+        Ok(Styles {
+            scope_global: parse_style(&self.scope_global)?,
+            scope_session: parse_style(&self.scope_session)?,
+            scope_local: parse_style(&self.scope_local)?,
+            scope_host: parse_style(&self.scope_host)?,
+            scope_project: parse_style(&self.scope_project)?,
+            header: parse_style(&self.header)?,
+            help: parse_style(&self.help)?,
+            text: parse_style(&self.text)?,
+            matched: parse_style(&self.matched)?,
+            selection: parse_style(&self.selection)?,
+            selected_text: selected(&self.selected_text)?,
+            selected_match: selected(&self.selected_match)?,
+            mark: parse_style(&self.mark)?,
+            selected_mark: selected(&self.mark)?,
+            time: parse_style(&self.time)?,
+            duration: parse_style(&self.duration)?,
+            runs: parse_style(&self.runs)?,
+            error: parse_style(&self.error)?,
+            selected_error: selected(&self.error)?,
+            prompt: parse_style(&self.prompt)?,
+            query: parse_style(&self.query)?,
+            placeholder: parse_style(&self.placeholder)?,
+            message: parse_style(&self.message)?,
+            warning: parse_style(&self.warning)?,
+            border: parse_style(&self.border)?,
+            label: parse_style(&self.label)?,
+            preview: parse_style(&self.preview)?,
+        })
+    }
+}
+
+/// A parsed `Theme`, with the selection background already applied to the
+/// `selected_*` styles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Styles {
+    pub scope_global: Style,
+    pub scope_session: Style,
+    pub scope_local: Style,
+    pub scope_host: Style,
+    pub scope_project: Style,
+    pub header: Style,
+    pub help: Style,
+    pub text: Style,
+    pub matched: Style,
+    pub selection: Style,
+    pub selected_text: Style,
+    pub selected_match: Style,
+    pub mark: Style,
+    pub selected_mark: Style,
+    pub time: Style,
+    pub duration: Style,
+    pub runs: Style,
+    pub error: Style,
+    pub selected_error: Style,
+    pub prompt: Style,
+    pub query: Style,
+    pub placeholder: Style,
+    pub message: Style,
+    pub warning: Style,
+    pub border: Style,
+    pub label: Style,
+    pub preview: Style,
+}
+
+impl Default for Styles {
+    fn default() -> Self {
+        Theme::default().styles().expect("the default theme is valid")
+    }
+}
+
+impl Styles {
+    pub fn scope(&self, scope: SearchScope) -> &Style {
+        match scope {
+            SearchScope::Global => &self.scope_global,
+            SearchScope::Session => &self.scope_session,
+            SearchScope::Local => &self.scope_local,
+            SearchScope::Host => &self.scope_host,
+            SearchScope::Project => &self.scope_project,
+        }
+    }
+}
+
+/// Parse a dotted style; later parts win, so `white.black` is black.
+///
+/// AIDEV-NOTE: `console::Style::from_dotted_str` silently skips unknown
+/// words and has no italic, so typos in the config would go unnoticed
+pub fn parse_style(spec: &str) -> Result<Style> {
+    // AIDEV-NOTE: styles only go to the interactive search's terminal;
+    // console would drop even bold and reverse under NO_COLOR, which the
+    // no-color theme relies on to show the selection
+    let mut style = Style::new().force_styling(true);
+    for part in spec.split('.').map(str::trim).filter(|part| !part.is_empty()) {
+        style = match part {
+            "black" => style.black(),
+            "red" => style.red(),
+            "green" => style.green(),
+            "yellow" => style.yellow(),
+            "blue" => style.blue(),
+            "magenta" => style.magenta(),
+            "cyan" => style.cyan(),
+            "white" => style.white(),
+            "bright" => style.bright(),
+            "on_black" => style.on_black(),
+            "on_red" => style.on_red(),
+            "on_green" => style.on_green(),
+            "on_yellow" => style.on_yellow(),
+            "on_blue" => style.on_blue(),
+            "on_magenta" => style.on_magenta(),
+            "on_cyan" => style.on_cyan(),
+            "on_white" => style.on_white(),
+            "on_bright" => style.on_bright(),
+            "bold" => style.bold(),
+            "dim" => style.dim(),
+            "italic" => style.italic(),
+            "underlined" => style.underlined(),
+            "reverse" => style.reverse(),
+            "strikethrough" => style.strikethrough(),
+            other => match other.strip_prefix("on_") {
+                Some(number) => match number.parse::<u8>() {
+                    Ok(color) => style.on_color256(color),
+                    Err(_) => bail!("unknown background '{}' in style '{}'", other, spec),
+                },
+                None => match other.parse::<u8>() {
+                    Ok(color) => style.color256(color),
+                    Err(_) => bail!("unknown color or attribute '{}' in style '{}'", other, spec),
+                },
+            },
+        };
+    }
+    Ok(style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn built_in_themes_parse() {
+        for name in Theme::BUILT_IN {
+            assert!(Theme::built_in(name).unwrap().styles().is_ok(), "{}", name);
+        }
+        assert!(parse_style("bold.on_17.208").is_ok());
+        assert!(parse_style("on_purple").is_err());
+    }
+
+    #[test]
+    fn custom_themes_build_on_their_base() {
+        let custom = BTreeMap::from([
+            ("paper".to_string(), table(&[("base", "light"), ("matched", "red")])),
+            ("ink".to_string(), table(&[("base", "paper"), ("text", "blue")])),
+            ("dark".to_string(), table(&[("text", "bright.white")])),
+            ("loop".to_string(), table(&[("base", "loop")])),
+        ]);
+
+        let ink = Theme::resolve("ink", &custom).unwrap();
+        assert_eq!(ink.matched, "red");
+        assert_eq!(ink.text, "blue");
+        assert_eq!(ink.selection, Theme::light().selection);
+
+        // Shadowing a built-in theme tweaks it rather than looping
+        let dark = Theme::resolve("dark", &custom).unwrap();
+        assert_eq!(dark.text, "bright.white");
+        assert_eq!(dark.matched, Theme::dark().matched);
+
+        assert!(Theme::resolve("loop", &custom).is_err());
+        assert!(Theme::resolve("solarized", &custom).is_err());
+        assert!(Theme::dark().with_overrides(&table(&[("matches", "red")])).is_err());
+    }
+}
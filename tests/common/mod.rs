@@ -0,0 +1,42 @@
+//! Running the `rehash` binary from the integration tests.
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// The `rehash` binary in session `s1`, reading `config` rather than the
+/// developer's own config file. A missing file means the defaults.
+pub fn command(config: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rehash"));
+    command.env("REHASH_CONFIG", config).env("REHASH_SESSION_ID", "s1");
+    command
+}
+
+/// `rehash --database <database>`, with a config file next to it that
+/// doesn't exist.
+pub fn with_database(database: &Path) -> Command {
+    let mut command = command(&database.with_extension("toml"));
+    command.arg("--database").arg(database);
+    command
+}
+
+/// Run `rehash` reading `config`, which may name its own database.
+pub fn configured(config: &Path, args: &[&str]) -> Output {
+    command(config).args(args).output().unwrap()
+}
+
+pub fn output(database: &Path, args: &[&str]) -> Output {
+    with_database(database).args(args).output().unwrap()
+}
+
+/// Run `rehash` against `database`, expecting it to succeed, and return what
+/// it printed.
+pub fn rehash(database: &Path, args: &[&str]) -> String {
+    stdout(with_database(database).args(args))
+}
+
+pub fn stdout(command: &mut Command) -> String {
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
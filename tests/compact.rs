@@ -0,0 +1,94 @@
+mod common;
+
+use chrono::{Duration, Utc};
+
+fn entry(command: &str, directory: &str, days_ago: i64) -> String {
+    serde_json::json!({
+        "command": command,
+        "timestamp": Utc::now() - Duration::days(days_ago),
+        "directory": directory,
+        "exit_code": 0,
+        "session_id": "s1",
+    })
+    .to_string()
+}
+
+#[test]
+fn compact_dedups_per_directory_and_drops_old_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    let lines = [
+        entry("make", "/src/a", 400),
+        entry("make", "/src/a", 3),
+        entry("make", "/src/b", 2),
+        entry("make", "/src/a", 1),
+        entry("ls", "/src/a", 0),
+    ];
+    std::fs::write(&database, lines.join("\n") + "\n").unwrap();
+
+    let compact = |extra: &[&str]| {
+        common::stdout(common::with_database(&database).args(["compact", "--dedup", "--older-than", "1y"]).args(extra))
+    };
+
+    let report = compact(&["--dry-run"]);
+    assert!(report.starts_with("Would remove 2 of 5 entries"), "{}", report);
+    assert_eq!(std::fs::read_to_string(&database).unwrap().lines().count(), 5);
+
+    let report = compact(&[]);
+    assert!(report.starts_with("Removed 2 of 5 entries"), "{}", report);
+
+    let entries: Vec<serde_json::Value> = std::fs::read_to_string(&database)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|e| {
+            (
+                e["command"].as_str().unwrap(),
+                e["directory"].as_str().unwrap(),
+                e["count"].as_u64().unwrap_or(1),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [("make", "/src/b", 1), ("make", "/src/a", 2), ("ls", "/src/a", 1)]
+    );
+}
+
+#[test]
+fn huge_ages_are_rejected_or_expire_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    std::fs::write(&database, entry("make", "/src/a", 400) + "\n").unwrap();
+
+    let compact = |age: &str| common::output(&database, &["compact", "--dry-run", "--older-than", age]);
+
+    let output = compact("99999999999999d");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("too long"));
+
+    let output = compact("1000000y");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Would remove 0 of 1 entries"));
+}
+
+#[cfg(unix)]
+#[test]
+fn rewrites_keep_the_file_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    let lines = [entry("make", "/src/a", 2), entry("make", "/src/a", 1)];
+    std::fs::write(&database, lines.join("\n") + "\n").unwrap();
+    std::fs::set_permissions(&database, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    common::rehash(&database, &["compact", "--dedup"]);
+    assert_eq!(std::fs::read_to_string(&database).unwrap().lines().count(), 1);
+
+    let mode = std::fs::metadata(&database).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
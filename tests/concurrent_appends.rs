@@ -0,0 +1,104 @@
+mod common;
+
+use std::thread;
+
+const WORKERS: usize = 16;
+const ADDS_PER_WORKER: usize = 20;
+
+// AIDEV-NOTE: commands well over PIPE_BUF (4096 bytes) are the ones that used
+// to interleave when several shells appended at the same moment
+fn long_command(worker: usize, n: usize) -> String {
+    format!("echo {}-{} {}", worker, n, "x".repeat(8192))
+}
+
+#[test]
+fn concurrent_adds_from_many_processes_are_all_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+
+    let handles: Vec<_> = (0..WORKERS)
+        .map(|worker| {
+            let database = database.clone();
+            thread::spawn(move || {
+                for n in 0..ADDS_PER_WORKER {
+                    let status = common::with_database(&database)
+                        .arg("add")
+                        .arg(long_command(worker, n))
+                        .env("REHASH_SESSION_ID", format!("worker_{}", worker))
+                        .status()
+                        .unwrap();
+                    assert!(status.success());
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let contents = std::fs::read_to_string(&database).unwrap();
+    let mut commands: Vec<String> = contents
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line)
+                .unwrap_or_else(|e| panic!("malformed line ({}): {:.80}", e, line));
+            entry["command"].as_str().unwrap().to_string()
+        })
+        .collect();
+
+    assert_eq!(commands.len(), WORKERS * ADDS_PER_WORKER);
+
+    let mut expected: Vec<String> = (0..WORKERS)
+        .flat_map(|worker| (0..ADDS_PER_WORKER).map(move |n| long_command(worker, n)))
+        .collect();
+    commands.sort();
+    expected.sort();
+    assert_eq!(commands, expected);
+}
+
+#[test]
+fn appends_during_session_clear_are_not_lost() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+
+    let rehash = |session: &str, args: &[&str]| {
+        let status = common::with_database(&database)
+            .args(args)
+            .env("REHASH_SESSION_ID", session)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    thread::scope(|scope| {
+        for worker in 0..4 {
+            let rehash = &rehash;
+            scope.spawn(move || {
+                for n in 0..ADDS_PER_WORKER {
+                    rehash("kept", &["add", &format!("keep {}-{}", worker, n)]);
+                    rehash("doomed", &["add", "doomed"]);
+                }
+            });
+        }
+        scope.spawn(|| {
+            for _ in 0..ADDS_PER_WORKER {
+                rehash("doomed", &["clear", "--scope", "session"]);
+            }
+        });
+    });
+    rehash("doomed", &["clear", "--scope", "session"]);
+
+    let contents = std::fs::read_to_string(&database).unwrap();
+    let commands: Vec<&str> = contents.lines().collect();
+    assert_eq!(commands.len(), 4 * ADDS_PER_WORKER);
+    assert!(commands.iter().all(|line| line.contains("\"keep ")));
+
+    // No temp files are left behind after the rewrites
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().contains(".tmp-"))
+        .collect();
+    assert!(leftovers.is_empty());
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::configured as rehash;
+use std::path::Path;
+
+#[test]
+fn config_file_supplies_defaults_that_flags_override() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+[search]
+mode = "prefix"
+max_results = 1
+
+[history]
+ignore = ["^ ", "secret"]
+
+[storage]
+database = {:?}
+"#,
+            database.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+
+    for command in ["git status", "git log", " git push", "export secret=1"] {
+        assert!(rehash(&config, &["add", command]).status.success());
+    }
+    // Ignored commands hand the shell no id, so it never calls `end`
+    let start = rehash(&config, &["start", "echo secret"]);
+    assert!(start.status.success());
+    assert!(start.stdout.is_empty());
+
+    let stored = std::fs::read_to_string(&database).unwrap();
+    assert_eq!(stored.lines().count(), 2);
+
+    let search = |args: &[&str]| {
+        let output = rehash(&config, args);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    // Prefix mode from the config: "log" is not a prefix of anything
+    assert_eq!(search(&["search", "log"]), "");
+    assert_eq!(search(&["search", "git"]).lines().count(), 1);
+    assert_eq!(search(&["search", "--mode", "fuzzy", "--max-results", "5", "log"]), "git log\n");
+}
+
+#[test]
+fn config_show_prints_the_effective_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "[ui]\nheight = \"40%\"\n\n[ranking]\nrecency = 2.0\n").unwrap();
+
+    let output = rehash(&config, &["--rank-weights", "success=0", "config", "show"]);
+    assert!(output.status.success());
+    let shown = String::from_utf8(output.stdout).unwrap();
+    assert!(shown.contains("height = \"40%\""));
+    assert!(shown.contains("recency = 2.0"));
+    assert!(shown.contains("success = 0.0"));
+    assert!(shown.contains("interactive_limit = 50"));
+    assert!(shown.contains("theme = \"dark\""));
+
+    // NO_COLOR picks the no-color theme unless the config names one
+    let no_color = |config: &Path| {
+        common::stdout(common::command(config).args(["config", "show"]).env("NO_COLOR", "1"))
+    };
+    assert!(no_color(&config).contains("theme = \"no-color\""));
+    let light = dir.path().join("light.toml");
+    std::fs::write(&light, "[ui]\ntheme = \"light\"\n").unwrap();
+    assert!(no_color(&light).contains("theme = \"light\""));
+
+    // The output is itself a valid config file
+    std::fs::write(&config, &shown).unwrap();
+    let again = rehash(&config, &["config", "show"]);
+    assert!(again.status.success());
+    assert_eq!(String::from_utf8(again.stdout).unwrap(), shown);
+}
+
+#[test]
+fn invalid_config_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+
+    for (text, message) in [
+        ("[search]\nscope = \"galaxy\"\n", "unknown variant"),
+        ("[serach]\n", "unknown field"),
+        ("[history]\nignore = [\"(\"]\n", "invalid ignore pattern"),
+        ("[ui.colors]\nmatched = \"yelow.bold\"\n", "yelow"),
+        ("[keymap.bindings]\nctrl-a = \"jump\"\n", "unknown action 'jump'"),
+        ("[ui]\ntheme = \"solarized\"\n", "unknown theme 'solarized'"),
+    ] {
+        std::fs::write(&config, text).unwrap();
+        let output = rehash(&config, &["config", "show"]);
+        assert!(!output.status.success(), "{}", text);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(message), "{}: {}", text, stderr);
+    }
+}
+
+#[test]
+fn invalid_config_does_not_stop_recording() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let database = dir.path().join("history.jsonl");
+    std::fs::write(&config, "[serach]\n").unwrap();
+    let database_arg = database.to_str().unwrap();
+
+    let add = rehash(&config, &["--database", database_arg, "add", "make"]);
+    assert!(add.status.success());
+    let warning = String::from_utf8(add.stderr).unwrap();
+    assert!(warning.starts_with("rehash: recording with the default settings"), "{}", warning);
+
+    let start = rehash(&config, &["--database", database_arg, "start", "ls"]);
+    assert!(start.status.success());
+    let id = String::from_utf8(start.stdout).unwrap();
+    let end = rehash(&config, &["--database", database_arg, "end", id.trim()]);
+    assert!(end.status.success());
+    assert!(end.stderr.is_empty());
+
+    assert_eq!(std::fs::read_to_string(&database).unwrap().lines().count(), 2);
+    // Everything else still reports the mistake
+    assert!(!rehash(&config, &["--database", database_arg, "search"]).status.success());
+}
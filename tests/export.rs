@@ -0,0 +1,94 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::rehash;
+use std::path::Path;
+
+fn entry(command: &str, directory: &str, exit_code: i32, minutes_ago: i64) -> String {
+    serde_json::json!({
+        "command": command,
+        "timestamp": Utc::now() - Duration::minutes(minutes_ago),
+        "directory": directory,
+        "exit_code": exit_code,
+        "session_id": "s1",
+        "duration_ms": 2500,
+    })
+    .to_string()
+}
+
+fn history(dir: &Path) -> std::path::PathBuf {
+    let database = dir.join("history.jsonl");
+    let lines = [
+        entry("make", "/src/app", 0, 30),
+        entry("echo \"a, b\"", "/src/app", 0, 20),
+        entry("for f in *; do\n  echo \\$f\ndone", "/tmp", 1, 10),
+        entry("make", "/src/app", 2, 0),
+    ];
+    std::fs::write(&database, lines.join("\n") + "\n").unwrap();
+    database
+}
+
+#[test]
+fn export_applies_search_filters_and_keeps_every_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = history(dir.path());
+
+    let jsonl = rehash(&database, &["export"]);
+    assert_eq!(jsonl.lines().count(), 4);
+
+    let csv = rehash(&database, &["export", "--format", "csv", "dir:/src/app"]);
+    let rows: Vec<_> = csv.lines().collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[0].starts_with("timestamp,command,directory,exit_code,duration_ms"));
+    assert!(rows[2].contains(",\"echo \"\"a, b\"\"\",/src/app,0,2500,"), "{}", rows[2]);
+
+    let tsv = rehash(&database, &["export", "-f", "tsv", "--mode", "exact", "echo exit:!0"]);
+    let rows: Vec<_> = tsv.lines().collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[1].contains("\tfor f in *; do\\n  echo \\\\$f\\ndone\t/tmp\t1\t"), "{}", rows[1]);
+
+    let bash = rehash(&database, &["export", "-f", "bash", "make"]);
+    assert_eq!(bash.lines().filter(|line| *line == "make").count(), 2);
+
+    let markdown = rehash(&database, &["export", "-f", "markdown", "exit:2"]);
+    assert!(markdown.starts_with("# Shell history\n"));
+    assert!(markdown.contains("in `/src/app`, exit 2, 2s\n\n```sh\nmake\n```\n"), "{}", markdown);
+}
+
+#[test]
+fn shell_exports_import_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = history(dir.path());
+
+    for (format, source) in [("bash", "bash"), ("zsh-extended", "zsh"), ("fish", "fish")] {
+        let file = dir.path().join(format);
+        rehash(&database, &["export", "-f", format, "--output", file.to_str().unwrap()]);
+
+        let copy = dir.path().join(format!("{}.jsonl", format));
+        let imported = rehash(&copy, &["import", source, file.to_str().unwrap()]);
+        assert_eq!(imported, "Imported 4 entries (0 already present)\n", "{}", format);
+
+        let commands = rehash(&copy, &["export", "-f", "bash"]);
+        let originals = rehash(&database, &["export", "-f", "bash"]);
+        assert_eq!(commands, originals, "{}", format);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn output_files_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let database = history(dir.path());
+    let file = dir.path().join("history.csv");
+
+    rehash(&database, &["export", "-f", "csv", "--output", file.to_str().unwrap(), "make"]);
+    let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(std::fs::read_to_string(&file).unwrap().lines().count(), 3);
+
+    // Overwriting truncates
+    rehash(&database, &["export", "-f", "csv", "--output", file.to_str().unwrap(), "exit:2"]);
+    assert_eq!(std::fs::read_to_string(&file).unwrap().lines().count(), 2);
+}
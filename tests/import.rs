@@ -0,0 +1,126 @@
+mod common;
+
+use common::rehash;
+use std::path::Path;
+
+fn stored(database: &Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(database)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn shell_histories_import_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    let bash = dir.path().join("bash_history");
+    let zsh = dir.path().join("zsh_history");
+    let fish = dir.path().join("fish_history");
+
+    std::fs::write(&bash, "ls -la\n#1700000000\ngit status\n#1700000060\ncat <<EOF\nhi\nEOF\n").unwrap();
+    std::fs::write(&zsh, ": 1700000100:2;make \\\n  test\n: 1700000200:0;git status\n").unwrap();
+    std::fs::write(&fish, "- cmd: echo a\\\\b\n  when: 1700000300\n  paths:\n    - a\\\\b\n").unwrap();
+
+    let import = |source: &str, path: &Path| rehash(&database, &["import", source, path.to_str().unwrap()]);
+    assert_eq!(import("bash", &bash), "Imported 3 entries (0 already present)\n");
+    assert_eq!(import("zsh", &zsh), "Imported 2 entries (0 already present)\n");
+    assert_eq!(import("fish", &fish), "Imported 1 entries (0 already present)\n");
+
+    // Re-importing a grown file only adds the new commands
+    let mut text = std::fs::read_to_string(&zsh).unwrap();
+    text.push_str(": 1700000400:0;cargo build\n");
+    std::fs::write(&zsh, text).unwrap();
+    assert_eq!(import("zsh", &zsh), "Imported 1 entries (2 already present)\n");
+    assert_eq!(import("bash", &bash), "Imported 0 entries (3 already present)\n");
+
+    let entries = stored(&database);
+    let commands: Vec<_> = entries.iter().map(|e| e["command"].as_str().unwrap()).collect();
+    assert_eq!(
+        commands,
+        ["ls -la", "git status", "cat <<EOF\nhi\nEOF", "make \n  test", "git status", "echo a\\b", "cargo build"]
+    );
+    assert_eq!(entries[3]["session_id"], "import-zsh");
+    assert_eq!(entries[3]["shell"], "zsh");
+    assert_eq!(entries[3]["duration_ms"], 2000);
+
+    // Imported commands are searchable like any other
+    assert_eq!(rehash(&database, &["search", "--mode", "exact", "make"]), "make \n  test\n");
+}
+
+#[test]
+fn untimed_history_imports_once_after_truncation() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    let bash = dir.path().join("bash_history");
+    let import = || rehash(&database, &["import", "bash", bash.to_str().unwrap()]);
+
+    std::fs::write(&bash, "ls\ngit status\nls\n").unwrap();
+    assert_eq!(import(), "Imported 2 entries (0 already present)\n");
+
+    // bash drops the oldest lines once HISTFILESIZE is reached
+    std::fs::write(&bash, "git status\nls\nmake\n").unwrap();
+    assert_eq!(import(), "Imported 1 entries (2 already present)\n");
+
+    let entries = stored(&database);
+    let mut runs: Vec<_> = entries
+        .iter()
+        .map(|e| (e["command"].as_str().unwrap(), e["count"].as_u64().unwrap_or(1)))
+        .collect();
+    runs.sort();
+    assert_eq!(runs, [("git status", 1), ("ls", 2), ("make", 1)]);
+
+    // Dated by the file rather than the epoch, so `compact --older-than` keeps them
+    let modified = chrono::DateTime::<chrono::Utc>::from(std::fs::metadata(&bash).unwrap().modified().unwrap());
+    for entry in &entries {
+        let timestamp: chrono::DateTime<chrono::Utc> = entry["timestamp"].as_str().unwrap().parse().unwrap();
+        assert!(timestamp < modified && modified - timestamp < chrono::Duration::minutes(1), "{}", entry);
+    }
+}
+
+#[test]
+fn missing_history_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("nope");
+    let output = common::output(&dir.path().join("history.jsonl"), &["import", "bash", missing.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to read"));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn atuin_database_imports_sessions_and_durations() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    let atuin = dir.path().join("atuin.db");
+
+    let conn = rusqlite::Connection::open(&atuin).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE history (
+            id TEXT PRIMARY KEY, timestamp INTEGER NOT NULL, duration INTEGER NOT NULL,
+            exit INTEGER NOT NULL, command TEXT NOT NULL, cwd TEXT NOT NULL,
+            session TEXT NOT NULL, hostname TEXT NOT NULL, deleted_at INTEGER
+        );
+        INSERT INTO history VALUES
+            ('a', 1700000000000000000, 1500000000, 0, 'cargo test', '/src/app', 's1', 'box:me', NULL),
+            ('b', 1700000100000000000, -1, 2, 'false', '/tmp', 's2', 'box:me', NULL),
+            ('c', 1700000200000000000, 0, 0, 'rm secret', '/tmp', 's2', 'box:me', 1700000300000000000);",
+    )
+    .unwrap();
+    drop(conn);
+
+    let import = || rehash(&database, &["import", "atuin", atuin.to_str().unwrap()]);
+    assert_eq!(import(), "Imported 2 entries (0 already present)\n");
+    assert_eq!(import(), "Imported 0 entries (2 already present)\n");
+
+    let entries = stored(&database);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["directory"], "/src/app");
+    assert_eq!(entries[0]["session_id"], "s1");
+    assert_eq!(entries[0]["hostname"], "box");
+    assert_eq!(entries[0]["user"], "me");
+    assert_eq!(entries[0]["duration_ms"], 1500);
+    assert_eq!(entries[1]["exit_code"], 2);
+    assert!(entries[1].get("duration_ms").is_none());
+}
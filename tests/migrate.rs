@@ -0,0 +1,42 @@
+mod common;
+
+use std::path::Path;
+
+/// Migrate two JSON Lines files into `target` twice over.
+fn check_migrate(target: &str, dir: &Path) {
+    let config = dir.join("config.toml");
+    // Targets may be sqlite:// URLs rather than paths
+    let rehash = |database: &str, args: &[&str]| {
+        common::stdout(common::command(&config).args(["--database", database]).args(args))
+    };
+    let old = dir.join("old.jsonl");
+    let other = dir.join("other.jsonl");
+    let (old, other) = (old.to_str().unwrap(), other.to_str().unwrap());
+    for command in ["git status", "make", "make"] {
+        rehash(old, &["add", command]);
+    }
+    rehash(other, &["add", "ls"]);
+
+    let migrate = || rehash(target, &["migrate", old, other]);
+    assert_eq!(migrate(), "Migrated 4 entries (0 already present)\n");
+    assert_eq!(migrate(), "Migrated 0 entries (4 already present)\n");
+
+    let exported = rehash(target, &["export", "-f", "bash"]);
+    let commands: Vec<_> = exported.lines().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(commands, ["git status", "make", "make", "ls"]);
+}
+
+#[test]
+fn migrate_into_jsonl_skips_what_is_present() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("history.jsonl");
+    check_migrate(target.to_str().unwrap(), dir.path());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migrate_into_sqlite_skips_what_is_present() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = format!("sqlite://{}", dir.path().join("history.db").display());
+    check_migrate(&target, dir.path());
+}
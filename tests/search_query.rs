@@ -0,0 +1,82 @@
+mod common;
+
+const HISTORY: &str = r#"{"command":"cargo build","timestamp":"2024-12-30T10:00:00Z","directory":"/src/app","exit_code":0,"session_id":"s1","hostname":"laptop","duration_ms":95000}
+{"command":"cargo test","timestamp":"2025-01-02T10:00:00Z","directory":"/src/app/core","exit_code":101,"session_id":"s1","hostname":"laptop","duration_ms":4000}
+{"command":"cargo run","timestamp":"2025-01-03T10:00:00Z","directory":"/tmp","exit_code":0,"session_id":"s2","hostname":"server"}
+{"command":"ls","timestamp":"2025-01-04T10:00:00Z","directory":"/src/app","exit_code":0,"session_id":"s2"}
+"#;
+
+fn search(database: &std::path::Path, query: &str) -> Vec<String> {
+    let output = common::stdout(common::with_database(database).args(["search", query]).env("REHASH_SESSION_ID", "s2"));
+
+    let mut commands: Vec<String> = output
+        .lines()
+        .map(str::to_string)
+        .collect();
+    commands.sort();
+    commands
+}
+
+#[test]
+fn field_filters_narrow_fuzzy_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    std::fs::write(&database, HISTORY).unwrap();
+
+    assert_eq!(search(&database, "cargo dir:/src/app"), ["cargo build", "cargo test"]);
+    assert_eq!(search(&database, "cargo exit:!0"), ["cargo test"]);
+    assert_eq!(search(&database, "host:laptop dur:>30s"), ["cargo build"]);
+    assert_eq!(search(&database, "session:current"), ["cargo run", "ls"]);
+    assert_eq!(
+        search(&database, "cargo before:2025-01-03T00:00:00Z after:2025-01-01T00:00:00Z"),
+        ["cargo test"]
+    );
+    // Half-typed filters are ignored rather than matched as text
+    assert_eq!(search(&database, "crgo after:"), ["cargo build", "cargo run", "cargo test"]);
+    // So are times out of range
+    for query in ["crgo after:99999999999999", "crgo before:9999999999999", "crgo after:+275760-01-01", "crgo dur:>9999999999999999w"] {
+        assert_eq!(search(&database, query), ["cargo build", "cargo run", "cargo test"], "{}", query);
+    }
+}
+
+#[test]
+fn host_scope_keeps_commands_recorded_here() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    // Elsewhere, and from before hostnames were recorded
+    std::fs::write(
+        &database,
+        r#"{"command":"cargo build","timestamp":"2025-01-01T10:00:00Z","directory":"/a","exit_code":0,"session_id":"s1","hostname":"elsewhere.invalid"}
+{"command":"cargo run","timestamp":"2025-01-02T10:00:00Z","directory":"/a","exit_code":0,"session_id":"s1"}
+"#,
+    )
+    .unwrap();
+    common::rehash(&database, &["add", "cargo test"]);
+
+    let search = |scope: &str| common::rehash(&database, &["search", "--scope", scope, "cargo"]);
+    assert_eq!(search("host"), "cargo test\n");
+    assert_eq!(search("global").lines().count(), 3);
+}
+
+#[test]
+fn identical_commands_are_collapsed_unless_disabled() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+    std::fs::write(
+        &database,
+        r#"{"command":"make","timestamp":"2025-01-01T10:00:00Z","directory":"/a","exit_code":0,"session_id":"s1"}
+{"command":"ls","timestamp":"2025-01-02T10:00:00Z","directory":"/a","exit_code":0,"session_id":"s1"}
+{"command":"make","timestamp":"2025-01-03T10:00:00Z","directory":"/b","exit_code":2,"session_id":"s1","count":3}
+"#,
+    )
+    .unwrap();
+
+    let list = |extra: &[&str]| common::stdout(common::with_database(&database).arg("search").args(extra));
+
+    // Newest first, like ranked results are best first
+    assert_eq!(list(&[]), "make\nls\n");
+    assert_eq!(list(&["mak"]), "make\n");
+    assert_eq!(list(&["--no-dedup"]), "make\nls\nmake\n");
+    assert_eq!(list(&["--no-dedup", "exit:0"]), "ls\nmake\n");
+    assert_eq!(list(&["--no-dedup", "mak"]), "make\nmake\n");
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::output as rehash;
+use std::path::Path;
+
+fn start(database: &Path, command: &str) -> String {
+    common::rehash(database, &["start", command]).trim().to_string()
+}
+
+#[test]
+fn start_and_end_record_exit_code_and_duration() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("history.jsonl");
+
+    let id = start(&database, "sleep 0.2");
+    assert!(dir.path().join("history.jsonl.pending").join(format!("{}.json", id)).exists());
+    // Nothing is stored until the command ends
+    assert!(!database.exists());
+
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(rehash(&database, &["end", &id, "--exit-code", "3"]).status.success());
+
+    let stored = std::fs::read_to_string(&database).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(stored.trim()).unwrap();
+    assert_eq!(entry["command"], "sleep 0.2");
+    assert_eq!(entry["exit_code"], 3);
+    assert_eq!(entry["session_id"], "s1");
+    assert!(entry["duration_ms"].as_u64().unwrap() >= 200, "{}", entry);
+    assert!(entry["end_timestamp"].is_string());
+
+    // Each start ends once
+    assert!(!rehash(&database, &["end", &id]).status.success());
+    assert_eq!(std::fs::read_to_string(&database).unwrap(), stored);
+}
+
+#[test]
+fn pending_commands_belong_to_their_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("a/history.jsonl");
+    let other = dir.path().join("b/history.jsonl");
+
+    let id = start(&database, "make");
+    assert!(!rehash(&other, &["end", &id]).status.success());
+    assert!(rehash(&database, &["end", &id]).status.success());
+    assert!(!other.exists());
+
+    for bad in ["", "../history", "a/b"] {
+        let end = rehash(&database, &["end", bad]);
+        assert!(!end.status.success());
+        assert!(String::from_utf8_lossy(&end.stderr).contains("Invalid entry id"), "{}", bad);
+    }
+}